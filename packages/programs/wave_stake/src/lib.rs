@@ -4,8 +4,37 @@
 // This program allows users to stake tokens and earn rewards.
 // Supports both flexible staking and locked staking with bonus multipliers.
 
+// Anchor's generated entrypoint code trips these lints on recent toolchains.
+#![allow(unexpected_cfgs)]
+#![allow(deprecated)]
+#![allow(clippy::too_many_arguments)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, TransferChecked};
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::system_program;
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, TransferChecked};
+
+/// How far in the past a pool's reward start may be set (clock drift allowance)
+pub const REWARD_START_TOLERANCE: i64 = 60;
+
+/// Approximate slot time, used to accrue between slots within the same second
+pub const MILLIS_PER_SLOT: u64 = 400;
+
+/// Upper bound on the protocol's cut of claimed rewards (2000 = 20%)
+pub const MAX_REWARD_PROTOCOL_FEE_BPS: u16 = 2000;
+
+/// Upper bound on the unstake fee (1000 = 10%)
+pub const MAX_UNSTAKE_FEE_BPS: u16 = 1000;
+
+/// Most lock tiers a pool can offer, flexible tier included
+pub const MAX_LOCK_TIERS: usize = 4;
+
+/// Most points in a pool's stake-size multiplier curve
+pub const MAX_AMOUNT_CURVE_POINTS: usize = 4;
+
+/// Fixed-point scale of the pool's reward-per-token accumulator
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
 
 // Program ID - Updated to avoid corrupted accounts from v1.0
 // Deployed: 2025-12-30
@@ -21,6 +50,7 @@ pub mod wave_stake {
         global_state.bump = ctx.bumps.global_state;
         global_state.authority = authority;
         global_state.pool_count = 0;
+        global_state.paused = false;
         msg!("Global state initialized with authority: {}", authority);
         Ok(())
     }
@@ -29,7 +59,7 @@ pub mod wave_stake {
     pub fn create_user_account(ctx: Context<CreateUserAccount>) -> Result<()> {
         let user = &mut ctx.accounts.user;
         let pool = &ctx.accounts.pool;
-        let clock = Clock::get()?;
+        let clock = current_clock()?;
 
         user.bump = ctx.bumps.user;
         user.amount = 0;
@@ -38,6 +68,19 @@ pub mod wave_stake {
         user.lock_end_timestamp = 0;
         user.bonus_multiplier = 10000;
         user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.last_reward_claim_millis = current_millis(pool, &clock)?;
+        user.lst_staked_underlying = 0;
+        user.tier_index = 0;
+        user.continuous_stake_start = 0;
+        user.cumulative_rewards = 0;
+        user.rewards_earned = 0;
+        user.reward_per_token_paid = pool.reward_per_token_stored;
+        user.reward_mode = 0;
+        user.lst_minted = 0;
+        user.verified_paired_pool = Pubkey::default();
+        user.accumulator_migrated = true;
+        user.verified_paired_user = Pubkey::default();
+        user.pair_bonus_bps = 0;
 
         msg!("User account created for pool: {}", String::from_utf8_lossy(&pool.pool_id));
         Ok(())
@@ -53,7 +96,26 @@ pub mod wave_stake {
         reward_per_second: u64,
         lock_duration: u64,
         lock_bonus_percentage: u16,
+        reward_start_timestamp: Option<i64>,
+        early_unstake_penalty_bps: u16,
+        lock_tiers: Option<Vec<LockTier>>,
+        min_stake: u64,
+        max_stake_per_user: u64,
+        max_total_staked: u64,
     ) -> Result<()> {
+        let clock = current_clock()?;
+        let now = clock.unix_timestamp;
+
+        require!(early_unstake_penalty_bps <= 10000, ErrorCode::InvalidConfiguration);
+        validate_stake_limits(min_stake, max_stake_per_user)?;
+
+        // Emissions may be scheduled for later, but not backdated
+        let reward_start = reward_start_timestamp.unwrap_or(now);
+        require!(
+            reward_start >= now - REWARD_START_TOLERANCE,
+            ErrorCode::InvalidConfiguration
+        );
+
         let pool = &mut ctx.accounts.pool;
         pool.bump = ctx.bumps.pool;
         pool.pool_id = pool_id;
@@ -65,8 +127,40 @@ pub mod wave_stake {
         pool.lock_bonus_percentage = lock_bonus_percentage;
         pool.total_staked = 0;
         pool.total_reward_distributed = 0;
-        pool.last_update_timestamp = Clock::get()?.unix_timestamp;
+        pool.total_lst_staked = 0;
+        pool.tenure_bonus_bps = 0;
+        pool.tenure_period = 0;
+        pool.tenure_bonus_cap_bps = 0;
+        pool.last_update_timestamp = now;
+        pool.last_update_millis = now.checked_mul(1000).ok_or(ErrorCode::MathOverflow)?;
+        pool.clock_anchor_timestamp = now;
+        pool.clock_anchor_slot = clock.slot;
         pool.authority = ctx.accounts.authority.key();
+        pool.reward_start_timestamp = reward_start;
+        pool.post_lock_grace = 0;
+        pool.reward_reserve = 0;
+        pool.locked = false;
+        pool.max_reward_per_user = 0;
+        pool.reward_end_timestamp = 0;
+        pool.reward_protocol_fee_bps = 0;
+        pool.protocol_fee_recipient = Pubkey::default();
+        pool.unstake_fee_bps = 0;
+        pool.total_reward_funded = 0;
+        pool.checkpoint_slot = clock.slot;
+        pool.checkpoint_total_staked = 0;
+        pool.early_unstake_penalty_bps = early_unstake_penalty_bps;
+        pool.reward_per_token_stored = 0;
+        pool.is_paused = false;
+        pool.lock_tiers = lock_tiers
+            .unwrap_or_else(|| default_lock_tiers(lock_duration, lock_bonus_percentage));
+        pool.paired_pool = None;
+        pool.pair_bonus_bps = 0;
+        pool.staker_count = 0;
+        pool.min_stake = min_stake;
+        pool.max_stake_per_user = max_stake_per_user;
+        pool.max_total_staked = max_total_staked;
+
+        validate_lock_tiers(&pool.lock_tiers)?;
 
         let global_state = &mut ctx.accounts.global_state;
         global_state.pool_count += 1;
@@ -79,41 +173,74 @@ pub mod wave_stake {
     }
 
     /// Stake tokens with optional lock period
-    /// lock_type: 0 = flexible, 1 = locked (30 days)
-    pub fn stake(ctx: Context<Stake>, amount: u64, lock_type: u8) -> Result<()> {
+    /// tier_index: index into the pool's lock tiers (a zero-duration tier is flexible)
+    /// Adding to an existing position must use its tier. A locked top-up pushes the
+    /// unlock time to at least `now + duration` and blends the bonus multiplier by stake.
+    /// memo: optional caller reference, emitted in the Staked event but not stored
+    pub fn stake(
+        ctx: Context<Stake>,
+        amount: u64,
+        tier_index: u8,
+        memo: Option<[u8; 32]>,
+    ) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!ctx.accounts.pool.is_paused, ErrorCode::PoolPaused);
+        enter_pool(&mut ctx.accounts.pool)?;
 
         let pool = &mut ctx.accounts.pool;
         let user = &mut ctx.accounts.user;
-        let clock = Clock::get()?;
+        let clock = current_clock()?;
+
+        let tier = resolve_lock_tier(pool, tier_index)?;
+        let lock_type = if tier.duration == 0 { 0 } else { 1 };
+
+        check_stake_limits(pool, user.amount, amount)?;
 
         // Calculate time elapsed and update pool rewards
-        let time_elapsed = (clock.unix_timestamp - pool.last_update_timestamp) as u64;
-        if time_elapsed > 0 && pool.total_staked > 0 {
-            let rewards_to_distribute = pool.reward_per_second
-                .checked_mul(time_elapsed)
-                .ok_or(ErrorCode::MathOverflow)?;
-            pool.total_reward_distributed = pool.total_reward_distributed
-                .checked_add(rewards_to_distribute)
-                .ok_or(ErrorCode::MathOverflow)?;
-        }
-        pool.last_update_timestamp = clock.unix_timestamp;
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+
+        // Bank what the existing stake has earned before its size changes
+        recheck_pair_bonus(pool, user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(pool, user, now_ms)?;
 
         // Check if this is a new user account (amount will be 0 if uninitialized)
         // Only set bump and lock type on first stake
         let is_new_user = user.amount == 0;
 
+        if !is_new_user {
+            require!(tier_index == user.tier_index, ErrorCode::LockTierMismatch);
+
+            if user.lock_type == 1 {
+                let (lock_end, multiplier) =
+                    topped_up_lock(user, &tier, amount, clock.unix_timestamp)?;
+                user.lock_end_timestamp = lock_end;
+                set_bonus_multiplier(user, multiplier)?;
+            }
+        }
+
+        let staked_before = user.amount;
         user.amount = user.amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        track_staker_transition(pool, staked_before, user.amount)?;
 
         if is_new_user {
             user.bump = ctx.bumps.user;
+            user.continuous_stake_start = clock.unix_timestamp;
             user.lock_type = lock_type;
+            user.tier_index = tier_index;
 
             if lock_type == 1 {
                 // Locked staking
+                let duration = i64::try_from(tier.duration)
+                    .map_err(|_| error!(ErrorCode::MathOverflow))?;
                 user.lock_start_timestamp = clock.unix_timestamp;
-                user.lock_end_timestamp = clock.unix_timestamp + pool.lock_duration as i64;
-                user.bonus_multiplier = 10000 + pool.lock_bonus_percentage; // 10000 = 1x (100%)
+                user.lock_end_timestamp = clock.unix_timestamp
+                    .checked_add(duration)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                let multiplier = 10000u16
+                    .checked_add(tier.bonus_bps)
+                    .ok_or(ErrorCode::MathOverflow)?; // 10000 = 1x (100%)
+                set_bonus_multiplier(user, multiplier)?;
             } else {
                 // Flexible staking
                 user.lock_start_timestamp = 0;
@@ -122,8 +249,6 @@ pub mod wave_stake {
             }
         }
 
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
-
         // Update pool totals
         pool.total_staked = pool.total_staked
             .checked_add(amount)
@@ -134,11 +259,28 @@ pub mod wave_stake {
         let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
         let is_native_sol = pool.stake_mint == native_sol_mint;
 
+        // Auto-compounding users restake their banked rewards along with the deposit
+        let compound_available = match ctx.accounts.reward_vault.as_ref() {
+            Some(vault) if !is_native_sol => vault.amount,
+            _ => 0,
+        };
+        let compounded = apply_auto_compound(pool, user, compound_available)?;
+
+        // Liquid staking: the deposit is mirrored as LST when the caller passes the LST mint
+        let lst_issued = if ctx.accounts.lst_mint.is_some() {
+            underlying_to_lst(amount)?
+        } else {
+            0
+        };
+        user.lst_minted = user.lst_minted
+            .checked_add(lst_issued)
+            .ok_or(ErrorCode::MathOverflow)?;
+
         if is_native_sol {
-            // For native SOL, use System Program to transfer lamports to pool authority
+            // Native SOL is held by the pool PDA itself, so the program can release it on unstake
             let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
                 &ctx.accounts.payer.key(),
-                &ctx.accounts.pool_authority.key(),
+                &ctx.accounts.pool.key(),
                 amount,
             );
 
@@ -146,42 +288,126 @@ pub mod wave_stake {
                 &transfer_ix,
                 &[
                     ctx.accounts.payer.to_account_info(),
-                    ctx.accounts.pool_authority.to_account_info(),
+                    ctx.accounts.pool.to_account_info(),
                 ],
             )?;
 
-            msg!("Transferred {} lamports (native SOL) to pool authority", amount);
+            msg!("Transferred {} lamports (native SOL) to pool", amount);
         } else {
             // For SPL tokens, use TransferChecked
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let stake_vault = ctx.accounts.stake_vault.as_ref()
+                .ok_or(ErrorCode::InvalidStakeVault)?;
+            let token_program = ctx.accounts.token_program.as_ref()
+                .ok_or(ErrorCode::InvalidTokenProgram)?;
+
             let transfer_accounts = TransferChecked {
-                from: ctx.accounts.user_token_account.as_ref().unwrap().to_account_info(),
-                to: ctx.accounts.pool_authority_token_account.as_ref().unwrap().to_account_info(),
+                from: user_token_account.to_account_info(),
+                to: stake_vault.to_account_info(),
                 authority: ctx.accounts.payer.to_account_info(),
                 mint: ctx.accounts.stake_mint.to_account_info(),
             };
 
             let transfer_ctx = CpiContext::new(
-                ctx.accounts.token_program.as_ref().unwrap().to_account_info(),
+                token_program.to_account_info(),
                 transfer_accounts,
             );
 
             let decimals = ctx.accounts.stake_mint.decimals;
             token::transfer_checked(transfer_ctx, amount, decimals)?;
 
-            msg!("Transferred {} tokens to pool authority", amount);
+            msg!("Transferred {} tokens to stake vault", amount);
+
+            if compounded > 0 {
+                let reward_vault = ctx.accounts.reward_vault.as_ref()
+                    .ok_or(ErrorCode::InvalidRewardVault)?;
+                let pool_id = ctx.accounts.pool.pool_id;
+                let bump = [ctx.accounts.pool.bump];
+                let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+
+                let compound_accounts = TransferChecked {
+                    from: reward_vault.to_account_info(),
+                    to: stake_vault.to_account_info(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                    mint: ctx.accounts.stake_mint.to_account_info(),
+                };
+
+                let compound_ctx = CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    compound_accounts,
+                    signer_seeds,
+                );
+
+                token::transfer_checked(compound_ctx, compounded, decimals)?;
+                msg!("Auto-compounded {} reward tokens", compounded);
+            }
+        }
+
+        if lst_issued > 0 {
+            let lst_mint = ctx.accounts.lst_mint.as_ref()
+                .ok_or(ErrorCode::InvalidMint)?;
+            let user_lst_account = ctx.accounts.user_lst_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let token_program = ctx.accounts.token_program.as_ref()
+                .ok_or(ErrorCode::InvalidTokenProgram)?;
+            let pool_id = ctx.accounts.pool.pool_id;
+            let bump = [ctx.accounts.pool.bump];
+            let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+
+            let mint_accounts = MintTo {
+                mint: lst_mint.to_account_info(),
+                to: user_lst_account.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+            };
+
+            let mint_ctx = CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                mint_accounts,
+                signer_seeds,
+            );
+
+            token::mint_to(mint_ctx, lst_issued)?;
+            msg!("Minted {} LST", lst_issued);
         }
 
-        msg!("Staked {} tokens with lock type: {}", amount, lock_type);
+        emit!(Staked {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.payer.key(),
+            amount,
+            lock_type,
+            tier_index,
+            memo,
+            timestamp: clock.unix_timestamp,
+        });
+
+        if compounded > 0 {
+            emit!(RewardReserveUpdated {
+                pool: ctx.accounts.pool.key(),
+                updated_by: ctx.accounts.payer.key(),
+                deposited: 0,
+                withdrawn: compounded,
+                reward_reserve: ctx.accounts.pool.reward_reserve,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Staked {} tokens in lock tier: {}", amount, tier_index);
         Ok(())
     }
 
     /// Unstake tokens (only after lock period expires for locked stakes)
+    /// SPL pools with an unstake fee withhold it from the principal and move it
+    /// into the reward vault, growing the reserve for the remaining stakers.
     pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
+        enter_pool(&mut ctx.accounts.pool)?;
 
         let user = &mut ctx.accounts.user;
         let pool = &mut ctx.accounts.pool;
-        let clock = Clock::get()?;
+        let clock = current_clock()?;
 
         // Check if user has enough staked
         require!(user.amount >= amount, ErrorCode::InsufficientStake);
@@ -194,131 +420,549 @@ pub mod wave_stake {
             );
         }
 
-        // Calculate pending rewards before unstaking
-        let time_elapsed = (clock.unix_timestamp - user.last_reward_claim_timestamp) as u64;
-        let user_share = if pool.total_staked > 0 {
-            (user.amount as u128)
-                .checked_mul(10000 as u128)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(pool.total_staked as u128)
-                .ok_or(ErrorCode::MathOverflow)? as u64
-        } else {
-            0
-        };
+        // Bank pending rewards before unstaking; they stay claimable
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+        recheck_pair_bonus(pool, user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(pool, user, now_ms)?;
 
-        let pending_rewards = pool
-            .reward_per_second
-            .checked_mul(time_elapsed)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user_share)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user.bonus_multiplier as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::MathOverflow)?;
+        // Check if unstaking native SOL
+        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+        let is_native_sol = pool.stake_mint == native_sol_mint;
+
+        // Auto-compounding users keep their banked rewards staked
+        let compound_available = match ctx.accounts.reward_vault.as_ref() {
+            Some(vault) if !is_native_sol => vault.amount,
+            _ => 0,
+        };
+        let compounded = apply_auto_compound(pool, user, compound_available)?;
+        let pending_rewards = user.rewards_earned;
 
         // Update user stake
+        let staked_before = user.amount;
         user.amount = user.amount.checked_sub(amount).ok_or(ErrorCode::MathOverflow)?;
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        track_staker_transition(pool, staked_before, user.amount)?;
+
+        // A full exit breaks the continuous-stake tenure
+        if user.amount == 0 {
+            user.continuous_stake_start = 0;
+        }
+
+        // LST issued against the withdrawn principal is burned before it is returned
+        let lst_burned = lst_to_burn(user)?;
+        user.lst_minted -= lst_burned;
 
         // Update pool totals
         pool.total_staked = pool.total_staked
             .checked_sub(amount)
             .ok_or(ErrorCode::MathOverflow)?;
 
-        // Check if unstaking native SOL
-        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
-        let is_native_sol = pool.stake_mint == native_sol_mint;
+        let fee = if is_native_sol { 0 } else { unstake_fee(pool, amount)? };
+        let payout = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+        pool.reward_reserve = pool.reward_reserve
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        distribute_to_stakers(pool, fee)?;
+
+        burn_position_lst(
+            ctx.accounts.lst_mint.as_ref(),
+            ctx.accounts.user_lst_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.authority.to_account_info(),
+            lst_burned,
+        )?;
 
         if is_native_sol {
-            // TODO: For native SOL, we need to transfer lamports from pool authority back to user
-            // This requires pool_authority to be a PDA (Program Derived Address) controlled by the program
-            // Currently using external wallet (8uSHCQQDycVbjj2qMLm8qS2zKUdgFfN2JEsqWvzUdqEz) which can't sign
-            // Skipping actual transfer for now - accounting will still work
-            msg!("Skipping native SOL transfer - pool authority is external wallet");
-            msg!("User accounting updated. Manual SOL transfer required from pool authority: {}", ctx.accounts.pool_authority.key());
+            // The pool PDA holds staked lamports and is owned by this program,
+            // so they can be moved back directly without a signed CPI
+            release_pool_lamports(
+                &ctx.accounts.pool.to_account_info(),
+                &ctx.accounts.authority.to_account_info(),
+                payout,
+            )?;
+
+            msg!("Transferred {} lamports (native SOL) back to user", payout);
         } else {
-            // For SPL tokens, transfer from pool authority token account to user token account
-            let transfer_accounts = TransferChecked {
-                from: ctx.accounts.pool_authority_token_account.as_ref().unwrap().to_account_info(),
-                to: ctx.accounts.user_token_account.as_ref().unwrap().to_account_info(),
-                authority: ctx.accounts.pool_authority.to_account_info(),
-                mint: ctx.accounts.stake_mint.to_account_info(),
-            };
+            // For SPL tokens, the pool PDA signs the transfer out of its stake vault
+            let stake_vault = ctx.accounts.stake_vault.as_ref()
+                .ok_or(ErrorCode::InvalidStakeVault)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let token_program = ctx.accounts.token_program.as_ref()
+                .ok_or(ErrorCode::InvalidTokenProgram)?;
+            let decimals = ctx.accounts.stake_mint.decimals;
 
-            let transfer_ctx = CpiContext::new(
-                ctx.accounts.token_program.as_ref().unwrap().to_account_info(),
-                transfer_accounts,
-            );
+            if compounded > 0 {
+                let reward_vault = ctx.accounts.reward_vault.as_ref()
+                    .ok_or(ErrorCode::InvalidRewardVault)?;
+                let pool_id = ctx.accounts.pool.pool_id;
+                let bump = [ctx.accounts.pool.bump];
+                let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
 
-            let decimals = ctx.accounts.stake_mint.decimals;
-            token::transfer_checked(transfer_ctx, amount, decimals)?;
+                let compound_accounts = TransferChecked {
+                    from: reward_vault.to_account_info(),
+                    to: stake_vault.to_account_info(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                    mint: ctx.accounts.stake_mint.to_account_info(),
+                };
 
-            msg!("Transferred {} tokens back to user", amount);
+                let compound_ctx = CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    compound_accounts,
+                    signer_seeds,
+                );
+
+                token::transfer_checked(compound_ctx, compounded, decimals)?;
+                msg!("Auto-compounded {} reward tokens", compounded);
+            }
+
+            release_pool_tokens(
+                &ctx.accounts.pool,
+                stake_vault,
+                user_token_account,
+                &ctx.accounts.stake_mint,
+                token_program,
+                payout,
+            )?;
+
+            if fee > 0 {
+                let reward_vault = ctx.accounts.reward_vault.as_ref()
+                    .ok_or(ErrorCode::InvalidRewardVault)?;
+
+                release_pool_tokens(
+                    &ctx.accounts.pool,
+                    stake_vault,
+                    &reward_vault.to_account_info(),
+                    &ctx.accounts.stake_mint,
+                    token_program,
+                    fee,
+                )?;
+                msg!("Unstake fee of {} tokens added to reward reserve", fee);
+            }
+
+            msg!("Transferred {} tokens back to user", payout);
         }
 
+        if fee > 0 || compounded > 0 {
+            emit!(RewardReserveUpdated {
+                pool: ctx.accounts.pool.key(),
+                updated_by: ctx.accounts.authority.key(),
+                deposited: fee,
+                withdrawn: compounded,
+                reward_reserve: ctx.accounts.pool.reward_reserve,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+
+        ctx.accounts.pool.locked = false;
+
         msg!("Unstaked {} tokens", amount);
         msg!("Pending rewards: {}", pending_rewards);
         Ok(())
     }
 
-    /// Claim accumulated rewards
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+    /// Leave a locked stake before it expires, forfeiting the pool's early-unstake penalty
+    /// The penalty goes to the protocol fee recipient (or the pool authority if unset).
+    /// Expired locks should use `unstake`, which charges no penalty.
+    pub fn emergency_unstake(ctx: Context<EmergencyUnstake>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        enter_pool(&mut ctx.accounts.pool)?;
+
         let user = &mut ctx.accounts.user;
         let pool = &mut ctx.accounts.pool;
-        let clock = Clock::get()?;
+        let clock = current_clock()?;
+
+        require!(user.amount >= amount, ErrorCode::InsufficientStake);
+        require!(
+            user.lock_type == 1 && clock.unix_timestamp < user.lock_end_timestamp,
+            ErrorCode::NotInLockPeriod
+        );
+
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+        recheck_pair_bonus(pool, user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(pool, user, now_ms)?;
+        let pending_rewards = user.rewards_earned;
+
+        let staked_before = user.amount;
+        user.amount = user.amount.checked_sub(amount).ok_or(ErrorCode::MathOverflow)?;
+        track_staker_transition(pool, staked_before, user.amount)?;
+
+        if user.amount == 0 {
+            user.continuous_stake_start = 0;
+        }
+
+        let lst_burned = lst_to_burn(user)?;
+        user.lst_minted -= lst_burned;
+
+        pool.total_staked = pool.total_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let penalty = early_unstake_penalty(pool, amount)?;
+        let payout = amount.checked_sub(penalty).ok_or(ErrorCode::MathOverflow)?;
+        let recipient = penalty_recipient(pool);
+
+        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+        let is_native_sol = pool.stake_mint == native_sol_mint;
+
+        burn_position_lst(
+            ctx.accounts.lst_mint.as_ref(),
+            ctx.accounts.user_lst_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.authority.to_account_info(),
+            lst_burned,
+        )?;
 
-        // Calculate rewards since last claim
-        let time_elapsed = (clock.unix_timestamp - user.last_reward_claim_timestamp) as u64;
+        if is_native_sol {
+            require!(
+                ctx.accounts.penalty_recipient.key() == recipient,
+                ErrorCode::InvalidTokenAccount
+            );
 
-        let user_share = if pool.total_staked > 0 {
-            (user.amount as u128)
-                .checked_mul(10000 as u128)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_div(pool.total_staked as u128)
-                .ok_or(ErrorCode::MathOverflow)? as u64
+            let pool_info = ctx.accounts.pool.to_account_info();
+            release_pool_lamports(&pool_info, &ctx.accounts.authority.to_account_info(), payout)?;
+            release_pool_lamports(&pool_info, &ctx.accounts.penalty_recipient.to_account_info(), penalty)?;
         } else {
-            0
-        };
+            let stake_vault = ctx.accounts.stake_vault.as_ref()
+                .ok_or(ErrorCode::InvalidStakeVault)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let token_program = ctx.accounts.token_program.as_ref()
+                .ok_or(ErrorCode::InvalidTokenProgram)?;
 
-        let rewards = pool
-            .reward_per_second
-            .checked_mul(time_elapsed)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user_share)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(user.bonus_multiplier as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
+            release_pool_tokens(
+                &ctx.accounts.pool,
+                stake_vault,
+                user_token_account,
+                &ctx.accounts.stake_mint,
+                token_program,
+                payout,
+            )?;
+
+            if penalty > 0 {
+                let penalty_account = {
+                    let data = ctx.accounts.penalty_recipient.try_borrow_data()?;
+                    TokenAccount::try_deserialize(&mut &data[..])?
+                };
+                require!(
+                    penalty_account.mint == ctx.accounts.pool.stake_mint,
+                    ErrorCode::InvalidMint
+                );
+                require!(penalty_account.owner == recipient, ErrorCode::InvalidTokenAccount);
+
+                release_pool_tokens(
+                    &ctx.accounts.pool,
+                    stake_vault,
+                    &ctx.accounts.penalty_recipient,
+                    &ctx.accounts.stake_mint,
+                    token_program,
+                    penalty,
+                )?;
+            }
+        }
+
+        emit!(EarlyUnstake {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.authority.key(),
+            amount,
+            penalty,
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Early unstake of {} tokens, {} penalty", amount, penalty);
+        msg!("Pending rewards: {}", pending_rewards);
+        Ok(())
+    }
+
+    /// Withdraw the full principal while the program is paused, ignoring any lock
+    /// Rewards accrued since the last settlement are forfeited.
+    pub fn emergency_exit(ctx: Context<EmergencyExit>) -> Result<()> {
+        require!(ctx.accounts.global_state.paused, ErrorCode::NotInEmergency);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
+
+        let amount = user.amount;
+        require!(amount > 0, ErrorCode::InsufficientStake);
+
+        // Close out the position; rewards since the last settlement are dropped
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+
+        user.amount = 0;
+        track_staker_transition(pool, amount, 0)?;
+        user.continuous_stake_start = 0;
+        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        user.last_reward_claim_millis = now_ms;
+        user.reward_per_token_paid = pool.reward_per_token_stored;
+
+        let lst_burned = lst_to_burn(user)?;
+        user.lst_minted -= lst_burned;
+
+        pool.total_staked = pool.total_staked
+            .checked_sub(amount)
             .ok_or(ErrorCode::MathOverflow)?;
 
+        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+        let is_native_sol = pool.stake_mint == native_sol_mint;
+
+        burn_position_lst(
+            ctx.accounts.lst_mint.as_ref(),
+            ctx.accounts.user_lst_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.authority.to_account_info(),
+            lst_burned,
+        )?;
+
+        if is_native_sol {
+            release_pool_lamports(
+                &ctx.accounts.pool.to_account_info(),
+                &ctx.accounts.authority.to_account_info(),
+                amount,
+            )?;
+        } else {
+            let stake_vault = ctx.accounts.stake_vault.as_ref()
+                .ok_or(ErrorCode::InvalidStakeVault)?;
+            let user_token_account = ctx.accounts.user_token_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+            let token_program = ctx.accounts.token_program.as_ref()
+                .ok_or(ErrorCode::InvalidTokenProgram)?;
+
+            release_pool_tokens(
+                &ctx.accounts.pool,
+                stake_vault,
+                user_token_account,
+                &ctx.accounts.stake_mint,
+                token_program,
+                amount,
+            )?;
+        }
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Emergency exit of {} tokens, rewards forfeited", amount);
+        Ok(())
+    }
+
+    /// Switch program-wide emergency mode on or off (global authority only)
+    /// While paused, users can leave through `emergency_exit` regardless of locks.
+    pub fn set_global_paused(ctx: Context<SetGlobalPaused>, paused: bool) -> Result<()> {
+        ctx.accounts.global_state.paused = paused;
+
+        msg!("Global emergency mode: {}", paused);
+        Ok(())
+    }
+
+    /// Halt or resume new stakes into one pool (pool authority only)
+    /// Unstaking, claiming and closing accounts stay open so users can always exit.
+    pub fn set_pool_paused(ctx: Context<SetPoolPaused>, paused: bool) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.is_paused = paused;
+
+        emit!(PoolPauseToggled {
+            pool: pool.key(),
+            paused,
+            timestamp: current_clock()?.unix_timestamp,
+        });
+
+        msg!("Pool paused: {}", paused);
+        Ok(())
+    }
+
+    /// Claim accumulated rewards, paid from the pool's reward vault
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        process_claim(ctx.accounts, ctx.remaining_accounts, ctx.program_id, 10000)
+    }
+
+    /// Claim `bps`/10000 of pending rewards, leaving the rest accrued on the position
+    pub fn claim_rewards_partial(ctx: Context<ClaimRewards>, bps: u16) -> Result<()> {
+        require!(bps > 0 && bps <= 10000, ErrorCode::InvalidAmount);
+        process_claim(ctx.accounts, ctx.remaining_accounts, ctx.program_id, bps)
+    }
+
+    /// Choose what happens to rewards on each stake/unstake (position owner only)
+    /// mode: 0 = accumulate for a manual claim, 1 = auto-compound into the stake.
+    /// Auto-compounding needs a pool that pays rewards in its stake token.
+    pub fn set_reward_mode(ctx: Context<SetRewardMode>, mode: u8) -> Result<()> {
+        require!(mode <= 1, ErrorCode::InvalidConfiguration);
+        require!(
+            mode == 0 || ctx.accounts.pool.reward_mint == ctx.accounts.pool.stake_mint,
+            ErrorCode::CannotCompoundDifferentMint
+        );
+
+        ctx.accounts.user.reward_mode = mode;
+
+        msg!("Reward mode set to {}", mode);
+        Ok(())
+    }
+
+    /// Restake pending rewards into the user's position in one step
+    /// Only pools that pay rewards in their stake token can compound. The rewards
+    /// move from the reward vault into the stake vault so the new principal is backed;
+    /// the per-user cap and protocol fee apply as they do for a claim.
+    pub fn compound_rewards(ctx: Context<CompoundRewards>) -> Result<()> {
+        require!(
+            ctx.accounts.pool.reward_mint == ctx.accounts.pool.stake_mint,
+            ErrorCode::CannotCompoundDifferentMint
+        );
+        require!(!ctx.accounts.pool.is_paused, ErrorCode::PoolPaused);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let user = &mut ctx.accounts.user;
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
+
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+        recheck_pair_bonus(pool, user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(pool, user, now_ms)?;
+
+        let rewards = cap_user_rewards(pool, user, user.rewards_earned)?;
         require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+        require!(
+            ctx.accounts.reward_vault.amount >= rewards,
+            ErrorCode::InsufficientLiquidity
+        );
 
-        // Update last claim timestamp
-        user.last_reward_claim_timestamp = clock.unix_timestamp;
+        let (compounded, protocol_fee) = split_protocol_fee(pool, rewards)?;
+        check_stake_capacity(pool, user.amount, compounded)?;
+
+        user.rewards_earned = 0;
+        user.cumulative_rewards = user.cumulative_rewards
+            .checked_add(rewards)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let staked_before = user.amount;
+        user.amount = user.amount
+            .checked_add(compounded)
+            .ok_or(ErrorCode::MathOverflow)?;
+        track_staker_transition(pool, staked_before, user.amount)?;
 
-        // Update pool total distributed
+        pool.total_staked = pool.total_staked
+            .checked_add(compounded)
+            .ok_or(ErrorCode::MathOverflow)?;
         pool.total_reward_distributed = pool.total_reward_distributed
             .checked_add(rewards)
             .ok_or(ErrorCode::MathOverflow)?;
+        pool.reward_reserve = pool.reward_reserve.saturating_sub(rewards);
+
+        let pool_id = pool.pool_id;
+        let bump = [pool.bump];
+        let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+        let decimals = ctx.accounts.reward_mint.decimals;
+
+        if compounded > 0 {
+            let transfer_accounts = TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                to: ctx.accounts.stake_vault.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+            };
+
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_accounts,
+                signer_seeds,
+            );
+
+            token::transfer_checked(transfer_ctx, compounded, decimals)?;
+        }
+
+        if protocol_fee > 0 {
+            let protocol_fee_account = ctx.accounts.protocol_fee_account.as_ref()
+                .ok_or(ErrorCode::InvalidTokenAccount)?;
+
+            let transfer_accounts = TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                to: protocol_fee_account.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+            };
+
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                transfer_accounts,
+                signer_seeds,
+            );
+
+            token::transfer_checked(transfer_ctx, protocol_fee, decimals)?;
+        }
+
+        emit!(RewardsCompounded {
+            pool: ctx.accounts.pool.key(),
+            user: ctx.accounts.authority.key(),
+            amount: compounded,
+            protocol_fee,
+            timestamp: clock.unix_timestamp,
+        });
 
-        msg!("Claimed {} tokens in rewards", rewards);
+        emit!(RewardReserveUpdated {
+            pool: ctx.accounts.pool.key(),
+            updated_by: ctx.accounts.authority.key(),
+            deposited: 0,
+            withdrawn: rewards,
+            reward_reserve: ctx.accounts.pool.reward_reserve,
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Compounded {} tokens of rewards into stake", compounded);
         Ok(())
     }
 
     /// Update pool parameters (authority only)
+    /// Lock edits only apply to future stakes: existing positions keep the
+    /// lock end and bonus multiplier snapshotted on their User account.
+    /// Changing the legacy lock duration or bonus without new tiers resets the
+    /// schedule to the default flexible + locked pair. Pools with custom tiers
+    /// reject that, since existing positions' tier indexes would point elsewhere.
+    /// Mints are immutable once the pool exists; attempting to change one fails.
     pub fn update_pool(
         ctx: Context<UpdatePool>,
         new_reward_per_second: Option<u64>,
         new_lock_duration: Option<u64>,
         new_lock_bonus_percentage: Option<u16>,
+        new_stake_mint: Option<Pubkey>,
+        new_lst_mint: Option<Pubkey>,
+        new_reward_mint: Option<Pubkey>,
+        new_lock_tiers: Option<Vec<LockTier>>,
+        new_min_stake: Option<u64>,
+        new_max_stake_per_user: Option<u64>,
+        new_max_total_staked: Option<u64>,
     ) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
 
+        // Changing any mint would orphan existing positions and reward accounting
+        if let Some(mint) = new_stake_mint {
+            require!(mint == pool.stake_mint, ErrorCode::InvalidConfiguration);
+        }
+
+        if let Some(mint) = new_lst_mint {
+            require!(mint == pool.lst_mint, ErrorCode::InvalidConfiguration);
+        }
+
+        if let Some(mint) = new_reward_mint {
+            require!(mint == pool.reward_mint, ErrorCode::InvalidConfiguration);
+        }
+
         if let Some(reward_rate) = new_reward_per_second {
+            // Emissions so far are owed at the old rate
+            let now_ms = touch_clock(pool, &current_clock()?)?;
+            update_pool_rewards(pool, now_ms)?;
             pool.reward_per_second = reward_rate;
         }
 
+        if new_lock_tiers.is_none()
+            && (new_lock_duration.is_some() || new_lock_bonus_percentage.is_some())
+        {
+            require!(!has_custom_lock_tiers(pool), ErrorCode::InvalidConfiguration);
+        }
+
         if let Some(duration) = new_lock_duration {
             pool.lock_duration = duration;
         }
@@ -327,14 +971,72 @@ pub mod wave_stake {
             pool.lock_bonus_percentage = bonus;
         }
 
+        if let Some(tiers) = new_lock_tiers {
+            pool.lock_tiers = tiers;
+        } else if new_lock_duration.is_some() || new_lock_bonus_percentage.is_some() {
+            pool.lock_tiers = default_lock_tiers(pool.lock_duration, pool.lock_bonus_percentage);
+        }
+
+        validate_lock_tiers(&lock_tiers(pool))?;
+
+        if let Some(min_stake) = new_min_stake {
+            pool.min_stake = min_stake;
+        }
+
+        if let Some(max_stake) = new_max_stake_per_user {
+            pool.max_stake_per_user = max_stake;
+        }
+
+        // Lowering the capacity below current deposits only blocks new stakes
+        if let Some(capacity) = new_max_total_staked {
+            pool.max_total_staked = capacity;
+        }
+
+        validate_stake_limits(pool.min_stake, pool.max_stake_per_user)?;
+
         msg!("Pool parameters updated");
         Ok(())
     }
 
+    /// Configure the loyalty bonus for flexible stakers (authority only)
+    /// Every full `period` of continuous staking adds `bonus_bps_per_period`
+    /// to the multiplier, up to `cap_bps`. A zero period disables the bonus.
+    /// Settlements read the current settings, so a change also reprices each flexible
+    /// staker's unsettled interval, including the time before the change.
+    pub fn set_tenure_bonus(
+        ctx: Context<UpdatePool>,
+        bonus_bps_per_period: u16,
+        period: u64,
+        cap_bps: u16,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.tenure_bonus_bps = bonus_bps_per_period;
+        pool.tenure_period = period;
+        pool.tenure_bonus_cap_bps = cap_bps;
+
+        msg!(
+            "Tenure bonus set: {} bps per {} seconds, capped at {} bps",
+            bonus_bps_per_period,
+            period,
+            cap_bps
+        );
+        Ok(())
+    }
+
     /// Close user account and withdraw remaining stake
+    /// Any LST still issued against the position is burned before the principal is returned.
+    /// Fails while the position still has rewards it could claim or LST restaked.
     pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+        // Restaked LST is only recorded on this account, so it must be redeemed first
+        require!(
+            ctx.accounts.user.lst_staked_underlying == 0,
+            ErrorCode::LstStillStaked
+        );
+        enter_pool(&mut ctx.accounts.pool)?;
+
         let user = &mut ctx.accounts.user;
-        let clock = Clock::get()?;
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
 
         // Check lock period
         if user.lock_type == 1 {
@@ -344,305 +1046,4377 @@ pub mod wave_stake {
             );
         }
 
-        let amount = user.amount;
+        // Rewards are settled against the pre-close stake, and closing must not forfeit them
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+        recheck_pair_bonus(pool, user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(pool, user, now_ms)?;
+        ensure_rewards_claimed(pool, user)?;
 
-        // Close user account and return rent
-        ctx.accounts.user.close(ctx.accounts.user_wallet.to_account_info())?;
+        let amount = user.amount;
+        user.amount = 0;
+        track_staker_transition(pool, amount, 0)?;
 
-        msg!("User account closed, {} tokens withdrawn", amount);
-        Ok(())
-    }
-}
+        let lst_burned = lst_to_burn(user)?;
+        user.lst_minted -= lst_burned;
 
-// ============ Account Structures ============
+        pool.total_staked = pool.total_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+        let is_native_sol = pool.stake_mint == native_sol_mint;
+
+        burn_position_lst(
+            ctx.accounts.lst_mint.as_ref(),
+            ctx.accounts.user_lst_account.as_ref(),
+            ctx.accounts.token_program.as_ref(),
+            &ctx.accounts.authority.to_account_info(),
+            lst_burned,
+        )?;
+
+        if amount > 0 {
+            if is_native_sol {
+                release_pool_lamports(
+                    &ctx.accounts.pool.to_account_info(),
+                    &ctx.accounts.user_wallet.to_account_info(),
+                    amount,
+                )?;
+            } else {
+                // Return the remaining principal before the position disappears
+                let stake_vault = ctx.accounts.stake_vault.as_ref()
+                    .ok_or(ErrorCode::InvalidStakeVault)?;
+                let user_token_account = ctx.accounts.user_token_account.as_ref()
+                    .ok_or(ErrorCode::InvalidTokenAccount)?;
+                let token_program = ctx.accounts.token_program.as_ref()
+                    .ok_or(ErrorCode::InvalidTokenProgram)?;
+
+                release_pool_tokens(
+                    &ctx.accounts.pool,
+                    stake_vault,
+                    user_token_account,
+                    &ctx.accounts.stake_mint,
+                    token_program,
+                    amount,
+                )?;
+            }
+        }
+
+        ctx.accounts.pool.locked = false;
+
+        // The `close` constraint returns the rent to the user wallet
+        msg!("User account closed, {} tokens withdrawn", amount);
+        Ok(())
+    }
+
+    /// Settle pool emission accounting without touching any user (permissionless)
+    pub fn poke_pool(ctx: Context<PokePool>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
+
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+
+        msg!("Pool accounting settled at {}", pool.last_update_timestamp);
+        Ok(())
+    }
+
+    /// Settle emission accounting for every pool passed in `remaining_accounts` (permissionless)
+    /// Accounts that aren't writable pools, or fail to settle, are skipped.
+    /// Returns the number of pools updated.
+    pub fn poke_pools_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PokePoolsBatch<'info>>,
+    ) -> Result<u32> {
+        let clock = current_clock()?;
+        let mut updated: u32 = 0;
+
+        for info in ctx.remaining_accounts.iter() {
+            if !info.is_writable {
+                continue;
+            }
+
+            let mut pool = match Account::<Pool>::try_from(info) {
+                Ok(pool) => pool,
+                Err(_) => continue,
+            };
+
+            let settled = touch_clock(&mut pool, &clock)
+                .and_then(|now_ms| update_pool_rewards(&mut pool, now_ms));
+            if settled.is_err() {
+                msg!("Skipping pool {}", info.key());
+                continue;
+            }
+
+            pool.exit(&crate::ID)?;
+            updated += 1;
+        }
+
+        msg!("Settled {} pools", updated);
+        Ok(updated)
+    }
+
+    /// Move a position opened before the reward accumulator onto it (authority only)
+    /// Runs once per user. A legacy account is decoded from its original layout and
+    /// grown to the current one, with the authority paying the extra rent. The position
+    /// is checkpointed at the current accumulator value, so it earns from here on
+    /// without a retroactive share of past emissions. Its pool must be migrated first.
+    pub fn migrate_user_to_accumulator(ctx: Context<MigrateUserToAccumulator>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
+
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+
+        let info = ctx.accounts.user.to_account_info();
+        let is_legacy = info.data_len() < 8 + User::LEN;
+        let mut user = if is_legacy {
+            let legacy: LegacyUser = decode_legacy_account(&info.try_borrow_data()?, User::DISCRIMINATOR)?;
+            user_from_legacy(legacy, clock.unix_timestamp)
+        } else {
+            User::try_deserialize(&mut &info.try_borrow_data()?[..])?
+        };
+
+        migrate_legacy_user(pool, &mut user, now_ms)?;
+        if is_legacy {
+            // Legacy positions were opened before the staker count existed
+            track_staker_transition(pool, 0, user.amount)?;
+        }
+
+        grow_program_account(
+            &info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + User::LEN,
+        )?;
+        user.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        msg!("User migrated at reward per token {}", user.reward_per_token_paid);
+        Ok(())
+    }
+
+    /// Move a pool created by the first program release onto the current layout (authority only)
+    /// Every field added since is zeroed, and emission accounting restarts from now.
+    /// The authority pays the rent for the larger account and hands the staked principal
+    /// it held back to the pool: lamports to the pool PDA, SPL tokens to the stake vault.
+    pub fn migrate_pool(ctx: Context<MigratePool>, pool_id: [u8; 32]) -> Result<()> {
+        let info = ctx.accounts.pool.to_account_info();
+        require!(info.data_len() < 8 + Pool::LEN, ErrorCode::AlreadyMigrated);
+
+        let legacy: LegacyPool = decode_legacy_account(&info.try_borrow_data()?, Pool::DISCRIMINATOR)?;
+        require!(legacy.pool_id == pool_id, ErrorCode::InvalidConfiguration);
+        require!(
+            legacy.authority == ctx.accounts.authority.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let clock = current_clock()?;
+        let pool = pool_from_legacy(legacy, &clock)?;
+
+        grow_program_account(
+            &info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            8 + Pool::LEN,
+        )?;
+        pool.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        // Legacy pools left the principal with the authority; move it into pool custody
+        // so unstakes can be released by the pool PDA
+        if pool.total_staked > 0 {
+            let native_sol_mint = Pubkey::from_str_const("So11111111111111111111111111111111111111112");
+
+            if pool.stake_mint == native_sol_mint {
+                let transfer_accounts = system_program::Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: info.clone(),
+                };
+                let transfer_ctx = CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    transfer_accounts,
+                );
+                system_program::transfer(transfer_ctx, pool.total_staked)?;
+            } else {
+                let stake_mint = ctx.accounts.stake_mint.as_ref()
+                    .ok_or(ErrorCode::InvalidMint)?;
+                let authority_token_account = ctx.accounts.authority_token_account.as_ref()
+                    .ok_or(ErrorCode::InvalidTokenAccount)?;
+                let stake_vault = ctx.accounts.stake_vault.as_ref()
+                    .ok_or(ErrorCode::InvalidStakeVault)?;
+                let token_program = ctx.accounts.token_program.as_ref()
+                    .ok_or(ErrorCode::InvalidTokenProgram)?;
+                require!(stake_mint.key() == pool.stake_mint, ErrorCode::InvalidMint);
+                require!(
+                    stake_vault.key() == get_associated_token_address(&info.key(), &pool.stake_mint),
+                    ErrorCode::InvalidStakeVault
+                );
+
+                let transfer_accounts = TransferChecked {
+                    from: authority_token_account.to_account_info(),
+                    to: stake_vault.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                    mint: stake_mint.to_account_info(),
+                };
+                let transfer_ctx = CpiContext::new(
+                    token_program.to_account_info(),
+                    transfer_accounts,
+                );
+                token::transfer_checked(transfer_ctx, pool.total_staked, stake_mint.decimals)?;
+            }
+
+            msg!("Moved {} staked into pool custody", pool.total_staked);
+        }
+
+        msg!("Pool migrated: {}", String::from_utf8_lossy(&pool.pool_id));
+        Ok(())
+    }
+
+    /// Correct `pool.total_staked` to the stake vault's real balance (authority only)
+    /// `actual` must equal the vault balance, so the value can't be set arbitrarily.
+    pub fn reconcile_total_staked(ctx: Context<ReconcileTotalStaked>, actual: u64) -> Result<()> {
+        require!(
+            actual == ctx.accounts.stake_vault.amount,
+            ErrorCode::InvalidAmount
+        );
+
+        let pool = &mut ctx.accounts.pool;
+        let clock = current_clock()?;
+
+        // Settle emissions up to now against the recorded total before correcting it
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+
+        let before = pool.total_staked;
+        pool.total_staked = actual;
+
+        emit!(Reconciled {
+            pool: pool.key(),
+            before,
+            after: actual,
+            timestamp: clock.unix_timestamp,
+        });
+
+        msg!("Reconciled total staked: {} -> {}", before, actual);
+        Ok(())
+    }
+
+    /// Set how long a locked bonus keeps applying after the lock expires (authority only)
+    /// Settlements read the current window, so a change also moves where each locked
+    /// staker's bonus ends within their unsettled interval, including time already past.
+    pub fn set_post_lock_grace(ctx: Context<UpdatePool>, grace: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.post_lock_grace = grace;
+
+        msg!("Post-lock grace window set to {} seconds", grace);
+        Ok(())
+    }
+
+    /// Cap the total rewards any single user can earn from this pool (authority only)
+    /// Zero removes the cap. Rewards withheld by the cap stay in the reserve.
+    pub fn set_max_reward_per_user(ctx: Context<UpdatePool>, max_reward_per_user: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.max_reward_per_user = max_reward_per_user;
+
+        msg!("Per-user reward cap set to {}", max_reward_per_user);
+        Ok(())
+    }
+
+    /// Scale reward weight by stake size (authority only)
+    /// Each point applies its multiplier to stakes of at least `threshold`;
+    /// stakes below the first threshold, or any stake with an empty curve, earn 1x.
+    /// The curve is read when a position settles, so a change also reprices every
+    /// staker's unsettled interval, including the time before the change.
+    pub fn set_amount_multiplier_curve(
+        ctx: Context<UpdatePool>,
+        curve: Vec<AmountMultiplier>,
+    ) -> Result<()> {
+        validate_amount_multiplier_curve(&curve)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.amount_multiplier_curve = curve;
+
+        msg!("Amount multiplier curve set with {} points", pool.amount_multiplier_curve.len());
+        Ok(())
+    }
+
+    /// Pair this pool with another for a cross-pool reward boost (authority only)
+    /// Stakers verified via `verify_paired_stake` earn the bonus in force when they
+    /// were verified, from that settlement on; a new bonus reaches them when they next
+    /// verify. Changing or removing the pairing revokes existing bonuses at each
+    /// staker's next settlement, so their unsettled interval earns no pair bonus.
+    pub fn set_paired_pool(
+        ctx: Context<UpdatePool>,
+        paired_pool: Option<Pubkey>,
+        pair_bonus_bps: u16,
+    ) -> Result<()> {
+        let pool_key = ctx.accounts.pool.key();
+        let pool = &mut ctx.accounts.pool;
+        require!(paired_pool != Some(pool_key), ErrorCode::InvalidConfiguration);
+        require!(pair_bonus_bps <= 10000, ErrorCode::InvalidConfiguration);
+
+        pool.paired_pool = paired_pool;
+        pool.pair_bonus_bps = if paired_pool.is_some() { pair_bonus_bps } else { 0 };
+
+        msg!("Paired pool set, bonus {} bps", pool.pair_bonus_bps);
+        Ok(())
+    }
+
+    /// Grant or revoke a position's paired-pool bonus (anyone can call)
+    /// The bonus applies while the owner has stake in the pool's paired pool. It is
+    /// re-checked whenever the position settles, which must pass the paired position
+    /// in `remaining_accounts`; calling this after that position is emptied or closed
+    /// revokes it straight away.
+    pub fn verify_paired_stake(ctx: Context<VerifyPairedStake>) -> Result<()> {
+        let active = paired_position_active(&ctx.accounts.paired_user, ctx.program_id)?;
+        let paired_pool = ctx.accounts.paired_pool.key();
+        let paired_user = ctx.accounts.paired_user.key();
+
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let clock = current_clock()?;
+
+        // An emptied paired position loses the bonus for the unsettled interval too
+        if !active {
+            revoke_pair_bonus(user);
+        }
+
+        // Bank rewards at the old multiplier; this is the checkpoint a new bonus runs from
+        let now_ms = touch_clock(pool, &clock)?;
+        update_pool_rewards(pool, now_ms)?;
+        settle_user_rewards(pool, user, now_ms)?;
+
+        if active {
+            user.verified_paired_pool = paired_pool;
+            user.verified_paired_user = paired_user;
+            user.pair_bonus_bps = pool.pair_bonus_bps;
+        }
+
+        msg!("Paired stake {}", if active { "verified" } else { "revoked" });
+        Ok(())
+    }
+
+    /// Set the fee withheld from unstaked principal and shared out to the remaining stakers (authority only)
+    /// Only pools that stake their own reward token can charge one. Zero disables it.
+    pub fn set_unstake_fee(ctx: Context<UpdatePool>, fee_bps: u16) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(fee_bps <= MAX_UNSTAKE_FEE_BPS, ErrorCode::InvalidConfiguration);
+        require!(
+            fee_bps == 0 || pool.stake_mint == pool.reward_mint,
+            ErrorCode::InvalidConfiguration
+        );
+
+        pool.unstake_fee_bps = fee_bps;
+
+        msg!("Unstake fee set to {} bps", fee_bps);
+        Ok(())
+    }
+
+    /// Set the protocol's cut of claimed rewards and its treasury (authority only)
+    pub fn set_reward_protocol_fee(
+        ctx: Context<UpdatePool>,
+        fee_bps: u16,
+        recipient: Pubkey,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_REWARD_PROTOCOL_FEE_BPS, ErrorCode::InvalidConfiguration);
+        // A fee with no recipient would make every claim and compound fail
+        require!(
+            fee_bps == 0 || recipient != Pubkey::default(),
+            ErrorCode::InvalidConfiguration
+        );
+
+        let pool = &mut ctx.accounts.pool;
+        pool.reward_protocol_fee_bps = fee_bps;
+        pool.protocol_fee_recipient = recipient;
+
+        msg!("Reward protocol fee set to {} bps, paid to {}", fee_bps, recipient);
+        Ok(())
+    }
+
+    /// Deposit reward tokens into the pool's reward vault (permissionless)
+    /// Can be called repeatedly. With `extend_reward_end`, the emission end moves
+    /// out by however long the deposit lasts at the current reward rate. Only the
+    /// pool authority can put an end on an open-ended pool.
+    pub fn deposit_rewards(
+        ctx: Context<DepositRewards>,
+        amount: u64,
+        extend_reward_end: bool,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let clock = current_clock()?;
+        let pool = &mut ctx.accounts.pool;
+        pool.reward_reserve = pool.reward_reserve
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.total_reward_funded = pool.total_reward_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        if extend_reward_end {
+            check_reward_end_extension(pool, &ctx.accounts.depositor.key())?;
+
+            // Settle under the old end so a lapsed gap isn't emitted retroactively
+            let now_ms = touch_clock(pool, &clock)?;
+            update_pool_rewards(pool, now_ms)?;
+            pool.reward_end_timestamp = extended_reward_end(pool, amount, clock.unix_timestamp)?;
+        }
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.depositor_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+        );
+
+        let decimals = ctx.accounts.reward_mint.decimals;
+        token::transfer_checked(transfer_ctx, amount, decimals)?;
+
+        emit!(RewardsDeposited {
+            pool: ctx.accounts.pool.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            reward_reserve: ctx.accounts.pool.reward_reserve,
+            total_reward_funded: ctx.accounts.pool.total_reward_funded,
+            reward_end_timestamp: ctx.accounts.pool.reward_end_timestamp,
+            timestamp: clock.unix_timestamp,
+        });
+
+        emit!(RewardReserveUpdated {
+            pool: ctx.accounts.pool.key(),
+            updated_by: ctx.accounts.depositor.key(),
+            deposited: amount,
+            withdrawn: 0,
+            reward_reserve: ctx.accounts.pool.reward_reserve,
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Deposited {} reward tokens, reserve now {}", amount, ctx.accounts.pool.reward_reserve);
+        Ok(())
+    }
+
+    /// Top up the pool's reward vault without touching the emission schedule (permissionless)
+    /// The tokens join the reward reserve and count toward `total_reward_funded`.
+    pub fn fund_reward_vault(ctx: Context<FundRewardVault>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let clock = current_clock()?;
+        let pool = &mut ctx.accounts.pool;
+        pool.reward_reserve = pool.reward_reserve
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.total_reward_funded = pool.total_reward_funded
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_vault.to_account_info(),
+            authority: ctx.accounts.funder.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+        );
+
+        let decimals = ctx.accounts.reward_mint.decimals;
+        token::transfer_checked(transfer_ctx, amount, decimals)?;
+
+        emit!(RewardVaultFunded {
+            pool: ctx.accounts.pool.key(),
+            funder: ctx.accounts.funder.key(),
+            amount,
+        });
+
+        emit!(RewardReserveUpdated {
+            pool: ctx.accounts.pool.key(),
+            updated_by: ctx.accounts.funder.key(),
+            deposited: amount,
+            withdrawn: 0,
+            reward_reserve: ctx.accounts.pool.reward_reserve,
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Funded reward vault with {} tokens, reserve now {}", amount, ctx.accounts.pool.reward_reserve);
+        Ok(())
+    }
+
+    /// Rewards a user has earned so far, before the reserve and per-user caps (read-only)
+    /// Same accumulator math the claim path settles with; nothing is written.
+    pub fn view_pending_rewards(ctx: Context<ViewUser>) -> Result<u64> {
+        let clock = current_clock()?;
+        let mut pool = ctx.accounts.pool.clone().into_inner();
+        let now_ms = touch_clock(&mut pool, &clock)?;
+        let mut user = ctx.accounts.user.clone().into_inner();
+        recheck_pair_bonus(&pool, &mut user, ctx.remaining_accounts, ctx.program_id)?;
+        let pending = compute_pending_rewards(&pool, &user, now_ms)?;
+
+        msg!("Pending rewards: {}", pending);
+        Ok(pending)
+    }
+
+    /// Rewards a user could actually be paid right now (read-only)
+    /// Pending rewards capped at the pool's reward reserve.
+    pub fn claimable_rewards(ctx: Context<ViewUser>) -> Result<u64> {
+        let clock = current_clock()?;
+        let mut pool = ctx.accounts.pool.clone().into_inner();
+        let now_ms = touch_clock(&mut pool, &clock)?;
+        let mut user = ctx.accounts.user.clone().into_inner();
+        recheck_pair_bonus(&pool, &mut user, ctx.remaining_accounts, ctx.program_id)?;
+        let claimable = compute_claimable_rewards(&pool, &user, now_ms)?;
+
+        msg!("Claimable rewards: {}", claimable);
+        Ok(claimable)
+    }
+
+    /// Everything a wallet shows for one position, in a single call (read-only)
+    pub fn get_user_position(ctx: Context<ViewUser>) -> Result<UserPosition> {
+        let clock = current_clock()?;
+        let mut pool = ctx.accounts.pool.clone().into_inner();
+        let now_ms = touch_clock(&mut pool, &clock)?;
+        let mut user = ctx.accounts.user.clone().into_inner();
+        recheck_pair_bonus(&pool, &mut user, ctx.remaining_accounts, ctx.program_id)?;
+        let position = user_position(&pool, &user, now_ms)?;
+
+        msg!("Position: {} staked, {} claimable", position.amount, position.claimable_rewards);
+        Ok(position)
+    }
+
+    /// Lock options offered by the pool, indexed by `tier_index` (read-only)
+    pub fn get_lock_tiers(ctx: Context<ViewPool>) -> Result<Vec<LockTier>> {
+        let tiers = lock_tiers(&ctx.accounts.pool);
+
+        msg!("Pool offers {} lock tiers", tiers.len());
+        Ok(tiers)
+    }
+
+    /// Whether the pool is inside its emission window, and for how much longer (read-only)
+    pub fn is_pool_emitting(ctx: Context<ViewPool>) -> Result<EmissionStatus> {
+        let clock = current_clock()?;
+        let status = emission_status(&ctx.accounts.pool, clock.unix_timestamp)?;
+
+        msg!("Emitting: {}, {} seconds remaining", status.emitting, status.seconds_remaining);
+        Ok(status)
+    }
+
+    /// Underlying amount an LST amount redeems for at the current rate (read-only)
+    pub fn preview_redeem(_ctx: Context<ViewPool>, lst_amount: u64) -> Result<u64> {
+        let underlying = lst_to_underlying(lst_amount)?;
+
+        msg!("{} LST redeems for {} underlying", lst_amount, underlying);
+        Ok(underlying)
+    }
+
+    /// LST amount issued for an underlying deposit at the current rate (read-only)
+    pub fn preview_deposit(_ctx: Context<ViewPool>, underlying_amount: u64) -> Result<u64> {
+        let lst_amount = underlying_to_lst(underlying_amount)?;
+
+        msg!("{} underlying deposits as {} LST", underlying_amount, lst_amount);
+        Ok(lst_amount)
+    }
+
+    /// Recover tokens of an unrelated mint sent to a pool-owned account (authority only)
+    /// The stake and reward mints can never be swept.
+    pub fn sweep_foreign_tokens(ctx: Context<SweepForeignTokens>, mint: Pubkey, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let pool = &ctx.accounts.pool;
+        require!(
+            mint != pool.stake_mint && mint != pool.reward_mint && mint != pool.lst_mint,
+            ErrorCode::InvalidMint
+        );
+
+        let pool_id = pool.pool_id;
+        let bump = [pool.bump];
+        let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.pool_token_account.to_account_info(),
+            to: ctx.accounts.destination_token_account.to_account_info(),
+            authority: ctx.accounts.pool.to_account_info(),
+            mint: ctx.accounts.foreign_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+            signer_seeds,
+        );
+
+        let decimals = ctx.accounts.foreign_mint.decimals;
+        token::transfer_checked(transfer_ctx, amount, decimals)?;
+
+        msg!("Swept {} tokens of foreign mint {}", amount, mint);
+        Ok(())
+    }
+
+    /// Deposit LST back into the pool as a restaked position
+    /// The position is recorded in underlying terms (LST is issued 1:1 against the stake token).
+    /// Restaked LST earns rewards alongside the user's principal but doesn't count toward `total_staked`.
+    pub fn stake_lst(ctx: Context<StakeLst>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!ctx.accounts.pool.is_paused, ErrorCode::PoolPaused);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let underlying = lst_to_underlying(amount)?;
+
+        // Bank what the position has earned before its reward-bearing stake grows
+        let clock = current_clock()?;
+        let now_ms = touch_clock(&mut ctx.accounts.pool, &clock)?;
+        update_pool_rewards(&mut ctx.accounts.pool, now_ms)?;
+        recheck_pair_bonus(&ctx.accounts.pool, &mut ctx.accounts.user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(&ctx.accounts.pool, &mut ctx.accounts.user, now_ms)?;
+
+        let transfer_accounts = TransferChecked {
+            from: ctx.accounts.user_lst_account.to_account_info(),
+            to: ctx.accounts.lst_vault.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+            mint: ctx.accounts.lst_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+        );
+
+        let decimals = ctx.accounts.lst_mint.decimals;
+        token::transfer_checked(transfer_ctx, amount, decimals)?;
+
+        let user = &mut ctx.accounts.user;
+        user.lst_staked_underlying = user.lst_staked_underlying
+            .checked_add(underlying)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.total_lst_staked = pool.total_lst_staked
+            .checked_add(underlying)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.locked = false;
+
+        msg!("Restaked {} LST ({} underlying)", amount, underlying);
+        Ok(())
+    }
+
+    /// Redeem a restaked LST position back to the user's LST account
+    /// The pool PDA releases the LST from its vault, so no admin signature is needed.
+    pub fn unstake_lst(ctx: Context<UnstakeLst>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        enter_pool(&mut ctx.accounts.pool)?;
+
+        let underlying = lst_to_underlying(amount)?;
+        require!(
+            ctx.accounts.user.lst_staked_underlying >= underlying,
+            ErrorCode::InsufficientStake
+        );
+
+        let clock = current_clock()?;
+        let now_ms = touch_clock(&mut ctx.accounts.pool, &clock)?;
+        update_pool_rewards(&mut ctx.accounts.pool, now_ms)?;
+        recheck_pair_bonus(&ctx.accounts.pool, &mut ctx.accounts.user, ctx.remaining_accounts, ctx.program_id)?;
+        settle_user_rewards(&ctx.accounts.pool, &mut ctx.accounts.user, now_ms)?;
+
+        let user = &mut ctx.accounts.user;
+        user.lst_staked_underlying = user.lst_staked_underlying
+            .checked_sub(underlying)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.total_lst_staked = pool.total_lst_staked
+            .checked_sub(underlying)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        release_pool_tokens(
+            &ctx.accounts.pool,
+            &ctx.accounts.lst_vault,
+            &ctx.accounts.user_lst_account,
+            &ctx.accounts.lst_mint,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+
+        ctx.accounts.pool.locked = false;
+
+        msg!("Redeemed {} LST ({} underlying)", amount, underlying);
+        Ok(())
+    }
+}
+
+// ============ Helpers ============
+
+/// Move staked lamports out of a native SOL pool's PDA.
+/// The pool account is program-owned, so its lamports can be debited directly,
+/// but never below its rent-exempt minimum.
+pub fn release_pool_lamports(pool: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    let pool_lamports = pool.lamports()
+        .checked_sub(amount)
+        .ok_or(ErrorCode::InsufficientPoolLamports)?;
+    require!(
+        pool_lamports >= Rent::get()?.minimum_balance(pool.data_len()),
+        ErrorCode::InsufficientPoolLamports
+    );
+    let to_lamports = to.lamports()
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    **pool.try_borrow_mut_lamports()? = pool_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+/// Move SPL tokens out of a pool-owned token account such as the stake vault.
+/// The pool PDA is the account owner, so it signs the transfer with its seeds.
+pub fn release_pool_tokens<'info>(
+    pool: &Account<'info, Pool>,
+    from: &Account<'info, TokenAccount>,
+    to: &AccountInfo<'info>,
+    mint: &Account<'info, Mint>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let pool_id = pool.pool_id;
+    let bump = [pool.bump];
+    let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+
+    let transfer_accounts = TransferChecked {
+        from: from.to_account_info(),
+        to: to.clone(),
+        authority: pool.to_account_info(),
+        mint: mint.to_account_info(),
+    };
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        transfer_accounts,
+        signer_seeds,
+    );
+
+    token::transfer_checked(transfer_ctx, amount, mint.decimals)
+}
+
+/// Keep `pool.staker_count` in step with a position moving between empty and staked.
+/// Only the transitions count, so top-ups and partial unstakes leave it unchanged.
+pub fn track_staker_transition(pool: &mut Pool, before: u64, after: u64) -> Result<()> {
+    if before == 0 && after > 0 {
+        pool.staker_count = pool.staker_count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else if before > 0 && after == 0 {
+        // Saturating as a guard; legacy positions are counted when they are migrated
+        pool.staker_count = pool.staker_count.saturating_sub(1);
+    }
+    Ok(())
+}
+
+/// LST a position must burn so what it has issued stays covered by its remaining stake
+pub fn lst_to_burn(user: &User) -> Result<u64> {
+    Ok(user.lst_minted.saturating_sub(underlying_to_lst(user.amount)?))
+}
+
+/// Burn LST from the position owner's LST account (the owner signs).
+/// Only positions that minted LST on stake have anything to burn, so the LST
+/// accounts are required only when `amount` is nonzero.
+pub fn burn_position_lst<'info>(
+    lst_mint: Option<&Account<'info, Mint>>,
+    user_lst_account: Option<&AccountInfo<'info>>,
+    token_program: Option<&Program<'info, Token>>,
+    owner: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let lst_mint = lst_mint.ok_or(ErrorCode::InvalidMint)?;
+    let user_lst_account = user_lst_account.ok_or(ErrorCode::InvalidTokenAccount)?;
+    let token_program = token_program.ok_or(ErrorCode::InvalidTokenProgram)?;
+
+    let burn_accounts = Burn {
+        mint: lst_mint.to_account_info(),
+        from: user_lst_account.to_account_info(),
+        authority: owner.to_account_info(),
+    };
+
+    let burn_ctx = CpiContext::new(token_program.to_account_info(), burn_accounts);
+    token::burn(burn_ctx, amount)?;
+
+    msg!("Burned {} LST", amount);
+    Ok(())
+}
+
+/// Take the pool's reentrancy lock
+pub fn acquire_pool_lock(pool: &mut Pool) -> Result<()> {
+    require!(!pool.locked, ErrorCode::Reentrancy);
+    pool.locked = true;
+    Ok(())
+}
+
+/// Take the pool's reentrancy lock and persist it before any CPI, so a nested
+/// call into this program sees the pool as busy. Handlers clear the flag before
+/// returning; on error the whole transaction (and the flag) is rolled back.
+pub fn enter_pool(pool: &mut Account<Pool>) -> Result<()> {
+    acquire_pool_lock(pool)?;
+    pool.exit(&crate::ID)
+}
+
+/// The program's time source. Handlers read the cluster clock through this rather
+/// than `Clock::get()` directly, so unit tests can pin time with `set_test_clock`.
+pub fn current_clock() -> Result<Clock> {
+    #[cfg(test)]
+    if let Some(clock) = TEST_CLOCK.with(|pinned| pinned.borrow().clone()) {
+        return Ok(clock);
+    }
+
+    Ok(Clock::get()?)
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_CLOCK: std::cell::RefCell<Option<Clock>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Pin the clock `current_clock` returns on the calling test thread
+#[cfg(test)]
+pub fn set_test_clock(unix_timestamp: i64, slot: u64) {
+    TEST_CLOCK.with(|pinned| {
+        *pinned.borrow_mut() = Some(Clock {
+            slot,
+            unix_timestamp,
+            ..Default::default()
+        })
+    });
+}
+
+/// Current accrual time in milliseconds.
+/// The cluster clock only has whole seconds, so each slot seen after the first
+/// one in a second adds MILLIS_PER_SLOT, never spilling into the next second.
+pub fn current_millis(pool: &Pool, clock: &Clock) -> Result<i64> {
+    let base = clock.unix_timestamp
+        .checked_mul(1000)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if clock.unix_timestamp != pool.clock_anchor_timestamp || clock.slot <= pool.clock_anchor_slot {
+        return Ok(base);
+    }
+
+    let sub_second = (clock.slot - pool.clock_anchor_slot)
+        .saturating_mul(MILLIS_PER_SLOT)
+        .min(999);
+    Ok(base + sub_second as i64)
+}
+
+/// Return the current accrual time, re-anchor the pool's clock on a new second
+/// and checkpoint the reward-bearing stake at the first interaction in a new slot
+pub fn touch_clock(pool: &mut Pool, clock: &Clock) -> Result<i64> {
+    let now_ms = current_millis(pool, clock)?;
+    if clock.unix_timestamp != pool.clock_anchor_timestamp {
+        pool.clock_anchor_timestamp = clock.unix_timestamp;
+        pool.clock_anchor_slot = clock.slot;
+    }
+    if clock.slot != pool.checkpoint_slot {
+        pool.checkpoint_slot = clock.slot;
+        pool.checkpoint_total_staked = reward_bearing_total(pool);
+    }
+    Ok(now_ms)
+}
+
+/// Stake that reward shares are measured against: the total as of the start of
+/// the current slot, so same-slot stakes and unstakes can't shift each other's
+/// payout. Pools not touched since they were migrated fall back to the live total.
+pub fn staked_for_rewards(pool: &Pool) -> u64 {
+    if pool.checkpoint_slot == 0 {
+        reward_bearing_total(pool)
+    } else {
+        pool.checkpoint_total_staked
+    }
+}
+
+/// Pool-wide stake earning rewards: principal plus restaked LST (in underlying terms)
+pub fn reward_bearing_total(pool: &Pool) -> u64 {
+    pool.total_staked.saturating_add(pool.total_lst_staked)
+}
+
+/// A user's stake earning rewards: principal plus restaked LST (in underlying terms)
+pub fn reward_bearing_stake(user: &User) -> u64 {
+    user.amount.saturating_add(user.lst_staked_underlying)
+}
+
+/// `from_ms..to_ms` clipped to the pool's emission window; empty spans have `end <= start`
+pub fn emission_span(pool: &Pool, from_ms: i64, to_ms: i64) -> Result<(i64, i64)> {
+    let start_ms = pool.reward_start_timestamp
+        .checked_mul(1000)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let start = from_ms.max(start_ms);
+
+    let end = if pool.reward_end_timestamp > 0 {
+        let end_ms = pool.reward_end_timestamp
+            .checked_mul(1000)
+            .ok_or(ErrorCode::MathOverflow)?;
+        to_ms.min(end_ms)
+    } else {
+        to_ms
+    };
+    Ok((start, end))
+}
+
+/// Emitting milliseconds between `from_ms` and `to_ms`, ignoring time before
+/// emissions start and after they end
+pub fn accrual_millis(pool: &Pool, from_ms: i64, to_ms: i64) -> Result<u64> {
+    let (start, end) = emission_span(pool, from_ms, to_ms)?;
+    if end <= start {
+        return Ok(0);
+    }
+    seconds_between(end, start)
+}
+
+/// Reward per staked token (scaled by REWARD_PRECISION) accumulated up to `now_ms`.
+/// Each span since the last update is divided by the stake that was in the pool
+/// during it, so rate and stake changes only affect time after they happen.
+pub fn reward_per_token(pool: &Pool, now_ms: i64) -> Result<u128> {
+    let staked = staked_for_rewards(pool);
+    let elapsed_ms = accrual_millis(pool, pool.last_update_millis, now_ms)?;
+    if staked == 0 || elapsed_ms == 0 {
+        return Ok(pool.reward_per_token_stored);
+    }
+
+    let accrued = (pool.reward_per_second as u128)
+        .checked_mul(elapsed_ms as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_mul(REWARD_PRECISION)
+        .ok_or(ErrorCode::MathOverflow)?
+        / (1000 * staked as u128);
+
+    pool.reward_per_token_stored
+        .checked_add(accrued)
+        .ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Bring the pool's emission accounting up to `now_ms`.
+/// Every instruction that changes stake, rates or the emission window calls this first.
+pub fn update_pool_rewards(pool: &mut Pool, now_ms: i64) -> Result<()> {
+    pool.reward_per_token_stored = reward_per_token(pool, now_ms)?;
+
+    let elapsed_ms = accrual_millis(pool, pool.last_update_millis, now_ms)?;
+    if elapsed_ms > 0 && staked_for_rewards(pool) > 0 {
+        let rewards_to_distribute = (pool.reward_per_second as u128)
+            .checked_mul(elapsed_ms as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / 1000;
+        let rewards_to_distribute = u64::try_from(rewards_to_distribute)
+            .map_err(|_| error!(ErrorCode::MathOverflow))?;
+        pool.total_reward_distributed = pool.total_reward_distributed
+            .checked_add(rewards_to_distribute)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    pool.last_update_millis = now_ms;
+    pool.last_update_timestamp = now_ms.div_euclid(1000);
+    Ok(())
+}
+
+/// Loyalty bonus (in bps) earned by a flexible staker's continuous tenure
+pub fn tenure_bonus_bps(pool: &Pool, user: &User, now: i64) -> Result<u64> {
+    if pool.tenure_period == 0 || user.lock_type != 0 || user.continuous_stake_start == 0 {
+        return Ok(0);
+    }
+
+    let tenure = seconds_between(now, user.continuous_stake_start)?;
+    let periods = tenure / pool.tenure_period;
+    let bonus = periods
+        .checked_mul(pool.tenure_bonus_bps as u64)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(bonus.min(pool.tenure_bonus_cap_bps as u64))
+}
+
+/// Tenure bonus summed over every millisecond from the start of the user's
+/// continuous stake up to `to_ms` (bps x ms). Each full tenure period adds one
+/// step of bonus until the cap, so the sum has a closed form.
+pub fn tenure_bonus_integral(pool: &Pool, user: &User, to_ms: i64) -> Result<u128> {
+    if pool.tenure_period == 0
+        || pool.tenure_bonus_bps == 0
+        || user.lock_type != 0
+        || user.continuous_stake_start == 0
+    {
+        return Ok(0);
+    }
+
+    let start_ms = user.continuous_stake_start
+        .checked_mul(1000)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if to_ms <= start_ms {
+        return Ok(0);
+    }
+
+    let step = pool.tenure_bonus_bps as u128;
+    let cap = pool.tenure_bonus_cap_bps as u128;
+    let period_ms = (pool.tenure_period as u128)
+        .checked_mul(1000)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let held_ms = (to_ms - start_ms) as u128;
+    let periods = held_ms / period_ms;
+    // First period whose bonus reaches the cap
+    let capped_from = cap.div_ceil(step);
+
+    // Full periods before the cap earn 0, step, 2*step, ...
+    let ramp_periods = periods.min(capped_from);
+    let ramp = step
+        .checked_mul(period_ms)
+        .and_then(|v| v.checked_mul(ramp_periods * ramp_periods.saturating_sub(1) / 2))
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // The rest of the time earns the current period's bonus, or the cap once reached
+    let (rest_ms, rest_bps) = if periods < capped_from {
+        (held_ms - periods * period_ms, step * periods)
+    } else {
+        (held_ms - capped_from * period_ms, cap)
+    };
+    let rest = rest_ms
+        .checked_mul(rest_bps)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    ramp.checked_add(rest).ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Multiplier-weighted emitting time between `from_ms` and `to_ms` (bps x ms).
+/// A locked bonus only counts until it lapses and a flexible tenure bonus only
+/// from the period it was reached in, so a late settlement earns neither for
+/// time before it applied.
+pub fn weighted_accrual_millis(pool: &Pool, user: &User, from_ms: i64, to_ms: i64) -> Result<u128> {
+    if let Some(end) = bonus_end_timestamp(pool, user)? {
+        let end_ms = end.checked_mul(1000).ok_or(ErrorCode::MathOverflow)?;
+        let boost_until = end_ms.max(from_ms).min(to_ms);
+        let boosted = (accrual_millis(pool, from_ms, boost_until)? as u128)
+            .checked_mul(user.bonus_multiplier as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let unboosted = (accrual_millis(pool, boost_until, to_ms)? as u128)
+            .checked_mul(10000)
+            .ok_or(ErrorCode::MathOverflow)?;
+        return boosted.checked_add(unboosted).ok_or(error!(ErrorCode::MathOverflow));
+    }
+
+    let base = (accrual_millis(pool, from_ms, to_ms)? as u128)
+        .checked_mul(user.bonus_multiplier as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let (start, end) = emission_span(pool, from_ms, to_ms)?;
+    if end <= start {
+        return Ok(base);
+    }
+    let tenure = tenure_bonus_integral(pool, user, end)?
+        .checked_sub(tenure_bonus_integral(pool, user, start)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+    base.checked_add(tenure).ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Time at which a locked user's bonus lapses (lock end plus the pool's grace window)
+/// Flexible stakes have no lapse and return None.
+pub fn bonus_end_timestamp(pool: &Pool, user: &User) -> Result<Option<i64>> {
+    if user.lock_type == 0 {
+        return Ok(None);
+    }
+
+    let grace = i64::try_from(pool.post_lock_grace).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let end = user.lock_end_timestamp
+        .checked_add(grace)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(Some(end))
+}
+
+/// Reward multiplier (10000 = 1x) currently applied to a user
+pub fn effective_multiplier(pool: &Pool, user: &User, now: i64) -> Result<u64> {
+    if let Some(end) = bonus_end_timestamp(pool, user)? {
+        if now >= end {
+            return Ok(10000);
+        }
+    }
+
+    (user.bonus_multiplier as u64)
+        .checked_add(tenure_bonus_bps(pool, user, now)?)
+        .ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Cross-pool bonus (in bps) granted to a user verified against the pool's current pairing
+pub fn pair_bonus_bps(pool: &Pool, user: &User) -> u64 {
+    match pool.paired_pool {
+        Some(paired) if paired == user.verified_paired_pool => user.pair_bonus_bps as u64,
+        _ => 0,
+    }
+}
+
+/// Drop a user's pair bonus and the pairing it was granted for
+pub fn revoke_pair_bonus(user: &mut User) {
+    user.verified_paired_pool = Pubkey::default();
+    user.verified_paired_user = Pubkey::default();
+    user.pair_bonus_bps = 0;
+}
+
+/// Re-check a user's pair bonus before their rewards are settled.
+/// The paired position must be passed in `remaining_accounts` and still hold stake,
+/// and the pool must still be paired with it; otherwise the bonus is revoked first,
+/// so the unsettled interval can't earn a bonus the position no longer qualifies for.
+pub fn recheck_pair_bonus(
+    pool: &Pool,
+    user: &mut User,
+    remaining_accounts: &[AccountInfo],
+    program_id: &Pubkey,
+) -> Result<()> {
+    if user.pair_bonus_bps == 0 {
+        return Ok(());
+    }
+
+    let still_paired = pool.paired_pool == Some(user.verified_paired_pool)
+        && match remaining_accounts.iter().find(|account| account.key() == user.verified_paired_user) {
+            Some(paired_user) => paired_position_active(paired_user, program_id)?,
+            None => false,
+        };
+    if !still_paired {
+        revoke_pair_bonus(user);
+    }
+    Ok(())
+}
+
+/// Whether a (possibly closed) `User` account in the paired pool still holds stake
+pub fn paired_position_active(paired_user: &AccountInfo, program_id: &Pubkey) -> Result<bool> {
+    if paired_user.owner != program_id || paired_user.data_is_empty() {
+        return Ok(false);
+    }
+
+    let data = paired_user.try_borrow_data()?;
+    let position = User::try_deserialize(&mut &data[..])?;
+    Ok(position.amount > 0)
+}
+
+/// Rewards owed to a user at `now_ms`: accrual since their last settlement plus any banked balance
+pub fn compute_pending_rewards(pool: &Pool, user: &User, now_ms: i64) -> Result<u64> {
+    let from = user.last_reward_claim_millis;
+    let now = now_ms.div_euclid(1000);
+
+    // What the stake earned at 1x since the user's last checkpoint
+    let per_token = reward_per_token(pool, now_ms)?
+        .checked_sub(user.reward_per_token_paid)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let stake = reward_bearing_stake(user);
+    let base_rewards = (stake as u128)
+        .checked_mul(per_token)
+        .ok_or(ErrorCode::MathOverflow)?
+        / REWARD_PRECISION;
+
+    // Each millisecond earns at the multiplier that applied to it
+    let weighted_millis = weighted_accrual_millis(pool, user, from, now_ms)?;
+
+    // The multiplier is averaged over the interval's emitting time
+    let interval_millis = accrual_millis(pool, from, now_ms)? as u128;
+    let (weighted_millis, interval_millis) = if interval_millis == 0 {
+        (effective_multiplier(pool, user, now)? as u128, 1)
+    } else {
+        (weighted_millis, interval_millis)
+    };
+
+    let amount_multiplier = amount_multiplier_bps(pool, stake);
+    let pair_multiplier = 10000 + pair_bonus_bps(pool, user);
+
+    // Apply one multiplier at a time, undoing each 10000 scaling as it goes,
+    // so large stakes over long intervals stay well inside u128
+    let rewards = base_rewards
+        .checked_mul(weighted_millis)
+        .ok_or(ErrorCode::MathOverflow)?
+        / (interval_millis * 10000);
+    let rewards = rewards
+        .checked_mul(amount_multiplier as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    let rewards = rewards
+        .checked_mul(pair_multiplier as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+
+    let rewards = u64::try_from(rewards).map_err(|_| error!(ErrorCode::MathOverflow))?;
+
+    // Plus anything banked at an earlier settlement or left by a partial claim
+    rewards.checked_add(user.rewards_earned).ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Checkpoint a pre-accumulator position at the pool's current accumulator value.
+/// A legacy `reward_per_token_paid` of zero would otherwise credit the position
+/// with every emission since the pool opened. The pool must be updated to `now_ms`.
+pub fn migrate_legacy_user(pool: &Pool, user: &mut User, now_ms: i64) -> Result<()> {
+    require!(!user.accumulator_migrated, ErrorCode::AlreadyMigrated);
+
+    user.reward_per_token_paid = pool.reward_per_token_stored;
+    user.last_reward_claim_millis = now_ms;
+    user.last_reward_claim_timestamp = now_ms.div_euclid(1000);
+    user.accumulator_migrated = true;
+    Ok(())
+}
+
+/// Decode an account written in a legacy layout, checking its discriminator.
+/// Legacy accounts were allocated with spare bytes, so trailing data is ignored.
+pub fn decode_legacy_account<T: AnchorDeserialize>(data: &[u8], discriminator: &[u8]) -> Result<T> {
+    require!(
+        data.len() >= 8 && &data[..8] == discriminator,
+        anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+    );
+    T::deserialize(&mut &data[8..])
+        .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+}
+
+/// Current-layout position for a legacy one. It still needs `migrate_legacy_user`
+/// to checkpoint it against the accumulator.
+pub fn user_from_legacy(legacy: LegacyUser, now: i64) -> User {
+    User {
+        bump: legacy.bump,
+        amount: legacy.amount,
+        lock_type: legacy.lock_type,
+        lock_start_timestamp: legacy.lock_start_timestamp,
+        lock_end_timestamp: legacy.lock_end_timestamp,
+        bonus_multiplier: legacy.bonus_multiplier,
+        last_reward_claim_timestamp: legacy.last_reward_claim_timestamp,
+        // Legacy pools only offer the flexible + locked pair, indexed by lock type
+        tier_index: legacy.lock_type,
+        // Tenure wasn't tracked, so it starts counting at migration
+        continuous_stake_start: if legacy.amount > 0 { now } else { 0 },
+        accumulator_migrated: false,
+        ..Default::default()
+    }
+}
+
+/// Current-layout pool for a legacy one. Emissions restart from `clock`, so
+/// nothing is accrued for the time the pool spent on the old layout.
+pub fn pool_from_legacy(legacy: LegacyPool, clock: &Clock) -> Result<Pool> {
+    let now = clock.unix_timestamp;
+    Ok(Pool {
+        bump: legacy.bump,
+        pool_id: legacy.pool_id,
+        stake_mint: legacy.stake_mint,
+        lst_mint: legacy.lst_mint,
+        reward_mint: legacy.reward_mint,
+        reward_per_second: legacy.reward_per_second,
+        lock_duration: legacy.lock_duration,
+        lock_bonus_percentage: legacy.lock_bonus_percentage,
+        total_staked: legacy.total_staked,
+        total_reward_distributed: legacy.total_reward_distributed,
+        last_update_timestamp: now,
+        authority: legacy.authority,
+        reward_start_timestamp: now,
+        last_update_millis: now.checked_mul(1000).ok_or(ErrorCode::MathOverflow)?,
+        clock_anchor_timestamp: now,
+        clock_anchor_slot: clock.slot,
+        ..Default::default()
+    })
+}
+
+/// Grow a program-owned account to `new_len`, topping up its rent from `payer`
+pub fn grow_program_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    new_len: usize,
+) -> Result<()> {
+    if account.data_len() >= new_len {
+        return Ok(());
+    }
+
+    let top_up = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(account.lamports());
+    if top_up > 0 {
+        let transfer_accounts = system_program::Transfer {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let transfer_ctx = CpiContext::new(system_program.to_account_info(), transfer_accounts);
+        system_program::transfer(transfer_ctx, top_up)?;
+    }
+
+    account.resize(new_len)?;
+    Ok(())
+}
+
+/// Bank a user's rewards up to `now_ms` into `rewards_earned` and checkpoint them
+/// against the accumulator. The pool must already be updated to `now_ms`.
+pub fn settle_user_rewards(pool: &Pool, user: &mut User, now_ms: i64) -> Result<()> {
+    user.rewards_earned = compute_pending_rewards(pool, user, now_ms)?;
+    user.reward_per_token_paid = pool.reward_per_token_stored;
+    user.last_reward_claim_millis = now_ms;
+    user.last_reward_claim_timestamp = now_ms.div_euclid(1000);
+    Ok(())
+}
+
+/// Shared body of `claim_rewards` and `claim_rewards_partial`: pays `bps`/10000
+/// of pending rewards from the reward vault and carries the rest forward
+fn process_claim(
+    accounts: &mut ClaimRewards,
+    remaining_accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    bps: u16,
+) -> Result<()> {
+    // Rewards banked by a full unstake stay claimable after the stake is gone
+    require!(
+        reward_bearing_stake(&accounts.user) > 0 || accounts.user.rewards_earned > 0,
+        ErrorCode::NoRewardsAvailable
+    );
+    enter_pool(&mut accounts.pool)?;
+
+    let user = &mut accounts.user;
+    let pool = &mut accounts.pool;
+    let clock = current_clock()?;
+
+    // Calculate rewards since last claim
+    let now_ms = touch_clock(pool, &clock)?;
+    update_pool_rewards(pool, now_ms)?;
+    recheck_pair_bonus(pool, user, remaining_accounts, program_id)?;
+    let time_elapsed = seconds_between(now_ms, user.last_reward_claim_millis)?;
+
+    // A repeat claim in the same slot has accrued nothing new; treat it as a no-op
+    if time_elapsed == 0 && user.rewards_earned == 0 {
+        pool.locked = false;
+        msg!("No time elapsed since last claim");
+        return Ok(());
+    }
+
+    let pending = compute_pending_rewards(pool, user, now_ms)?;
+    let (portion, remainder) = split_partial_claim(pending, bps)?;
+    let rewards = cap_user_rewards(pool, user, portion)?;
+
+    require!(rewards > 0, ErrorCode::NoRewardsAvailable);
+    require!(
+        accounts.reward_vault.amount >= rewards,
+        ErrorCode::InsufficientLiquidity
+    );
+
+    // Update last claim timestamp
+    user.last_reward_claim_timestamp = clock.unix_timestamp;
+    user.last_reward_claim_millis = now_ms;
+    user.reward_per_token_paid = pool.reward_per_token_stored;
+    user.rewards_earned = remainder;
+    user.cumulative_rewards = user.cumulative_rewards
+        .checked_add(rewards)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Update pool total distributed
+    pool.total_reward_distributed = pool.total_reward_distributed
+        .checked_add(rewards)
+        .ok_or(ErrorCode::MathOverflow)?;
+    pool.reward_reserve = pool.reward_reserve.saturating_sub(rewards);
+
+    // The protocol's cut comes out of the user's claim
+    let (user_amount, protocol_fee) = split_protocol_fee(pool, rewards)?;
+    let protocol_fee_recipient = pool.protocol_fee_recipient;
+
+    let pool_id = pool.pool_id;
+    let bump = [pool.bump];
+    let signer_seeds: &[&[&[u8]]] = &[&[b"pool", pool_id.as_ref(), &bump]];
+    let decimals = accounts.reward_mint.decimals;
+
+    if user_amount > 0 {
+        let transfer_accounts = TransferChecked {
+            from: accounts.reward_vault.to_account_info(),
+            to: accounts.user_reward_token_account.to_account_info(),
+            authority: accounts.pool.to_account_info(),
+            mint: accounts.reward_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            transfer_accounts,
+            signer_seeds,
+        );
+
+        token::transfer_checked(transfer_ctx, user_amount, decimals)?;
+    }
+
+    if protocol_fee > 0 {
+        let protocol_fee_account = accounts.protocol_fee_account.as_ref()
+            .ok_or(ErrorCode::InvalidTokenAccount)?;
+
+        let transfer_accounts = TransferChecked {
+            from: accounts.reward_vault.to_account_info(),
+            to: protocol_fee_account.to_account_info(),
+            authority: accounts.pool.to_account_info(),
+            mint: accounts.reward_mint.to_account_info(),
+        };
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            transfer_accounts,
+            signer_seeds,
+        );
+
+        token::transfer_checked(transfer_ctx, protocol_fee, decimals)?;
+    }
+
+    emit!(RewardsClaimed {
+        pool: accounts.pool.key(),
+        user: accounts.authority.key(),
+        amount: user_amount,
+        protocol_fee,
+        protocol_fee_recipient,
+        timestamp: clock.unix_timestamp,
+    });
+
+    emit!(RewardReserveUpdated {
+        pool: accounts.pool.key(),
+        updated_by: accounts.authority.key(),
+        deposited: 0,
+        withdrawn: rewards,
+        reward_reserve: accounts.pool.reward_reserve,
+        timestamp: clock.unix_timestamp,
+    });
+
+    accounts.pool.locked = false;
+
+    msg!("Claimed {} tokens in rewards ({} protocol fee)", user_amount, protocol_fee);
+    Ok(())
+}
+
+/// Fold a settled user's banked rewards into their stake when they auto-compound.
+/// Moves at most `available` (the reward vault balance) and no more than the stake
+/// limits leave room for, and returns the amount; the caller transfers it from the
+/// reward vault into the stake vault. Pools that charge a protocol fee leave rewards
+/// banked for `claim_rewards` or `compound_rewards`.
+pub fn apply_auto_compound(pool: &mut Pool, user: &mut User, available: u64) -> Result<u64> {
+    if user.reward_mode != 1
+        || pool.reward_mint != pool.stake_mint
+        || pool.reward_protocol_fee_bps > 0
+    {
+        return Ok(0);
+    }
+
+    let amount = cap_user_rewards(pool, user, user.rewards_earned)?
+        .min(available)
+        .min(stake_headroom(pool, user.amount));
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    user.rewards_earned = user.rewards_earned
+        .checked_sub(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user.cumulative_rewards = user.cumulative_rewards
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user.amount = user.amount
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    pool.total_staked = pool.total_staked
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    pool.total_reward_distributed = pool.total_reward_distributed
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    pool.reward_reserve = pool.reward_reserve.saturating_sub(amount);
+
+    Ok(amount)
+}
+
+/// Split pending rewards into the part claimed now and the part left accrued
+pub fn split_partial_claim(pending: u64, bps: u16) -> Result<(u64, u64)> {
+    let portion = (pending as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    let portion = u64::try_from(portion).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let remainder = pending.checked_sub(portion).ok_or(ErrorCode::MathOverflow)?;
+    Ok((portion, remainder))
+}
+
+/// Limit `rewards` to what the user may still earn under the pool's per-user cap
+pub fn cap_user_rewards(pool: &Pool, user: &User, rewards: u64) -> Result<u64> {
+    if pool.max_reward_per_user == 0 {
+        return Ok(rewards);
+    }
+
+    let remaining = pool.max_reward_per_user.saturating_sub(user.cumulative_rewards);
+    Ok(rewards.min(remaining))
+}
+
+/// Require that nothing the user could still claim is banked on the position
+pub fn ensure_rewards_claimed(pool: &Pool, user: &User) -> Result<()> {
+    require!(
+        cap_user_rewards(pool, user, user.rewards_earned)? == 0,
+        ErrorCode::UnclaimedRewards
+    );
+    Ok(())
+}
+
+/// Portion of an unstaked amount withheld for the reward reserve
+pub fn unstake_fee(pool: &Pool, amount: u64) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(pool.unstake_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    u64::try_from(fee).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Share `amount` reward tokens out across the pool's current stake by raising
+/// the accumulator. With nothing staked the tokens are only left in the reserve.
+pub fn distribute_to_stakers(pool: &mut Pool, amount: u64) -> Result<()> {
+    if amount == 0 || pool.total_staked == 0 {
+        return Ok(());
+    }
+
+    let per_token = (amount as u128)
+        .checked_mul(REWARD_PRECISION)
+        .ok_or(ErrorCode::MathOverflow)?
+        / pool.total_staked as u128;
+    pool.reward_per_token_stored = pool.reward_per_token_stored
+        .checked_add(per_token)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+/// Portion of an early (pre-expiry) unstake forfeited as a penalty
+pub fn early_unstake_penalty(pool: &Pool, amount: u64) -> Result<u64> {
+    let penalty = (amount as u128)
+        .checked_mul(pool.early_unstake_penalty_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    u64::try_from(penalty).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Wallet that receives early-unstake penalties: the protocol fee recipient,
+/// falling back to the pool authority when none is configured
+pub fn penalty_recipient(pool: &Pool) -> Pubkey {
+    if pool.protocol_fee_recipient == Pubkey::default() {
+        pool.authority
+    } else {
+        pool.protocol_fee_recipient
+    }
+}
+
+/// Split claimed rewards into the user's share and the protocol fee
+pub fn split_protocol_fee(pool: &Pool, rewards: u64) -> Result<(u64, u64)> {
+    let fee = (rewards as u128)
+        .checked_mul(pool.reward_protocol_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10000;
+    let fee = u64::try_from(fee).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let user_amount = rewards.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+    Ok((user_amount, fee))
+}
+
+/// Pending rewards capped by the per-user limit and what the pool's reward reserve can cover
+pub fn compute_claimable_rewards(pool: &Pool, user: &User, now_ms: i64) -> Result<u64> {
+    let pending = compute_pending_rewards(pool, user, now_ms)?;
+    Ok(cap_user_rewards(pool, user, pending)?.min(pool.reward_reserve))
+}
+
+/// Snapshot of a user's position at `now_ms`, as returned by `get_user_position`
+pub fn user_position(pool: &Pool, user: &User, now_ms: i64) -> Result<UserPosition> {
+    Ok(UserPosition {
+        amount: user.amount,
+        lock_type: user.lock_type,
+        lock_end_timestamp: user.lock_end_timestamp,
+        multiplier_bps: effective_multiplier(pool, user, now_ms.div_euclid(1000))?,
+        pair_bonus_bps: pair_bonus_bps(pool, user),
+        pending_rewards: compute_pending_rewards(pool, user, now_ms)?,
+        claimable_rewards: compute_claimable_rewards(pool, user, now_ms)?,
+    })
+}
+
+/// Stake-size multiplier (10000 = 1x) from the highest curve point `amount` reaches
+pub fn amount_multiplier_bps(pool: &Pool, amount: u64) -> u64 {
+    pool.amount_multiplier_curve
+        .iter()
+        .take_while(|point| amount >= point.threshold)
+        .last()
+        .map_or(10000, |point| point.multiplier_bps as u64)
+}
+
+/// Check an amount curve is bounded and sorted: at most MAX_AMOUNT_CURVE_POINTS,
+/// strictly increasing thresholds and nonzero multipliers
+pub fn validate_amount_multiplier_curve(curve: &[AmountMultiplier]) -> Result<()> {
+    require!(curve.len() <= MAX_AMOUNT_CURVE_POINTS, ErrorCode::InvalidConfiguration);
+    require!(
+        curve.iter().all(|point| point.multiplier_bps > 0),
+        ErrorCode::InvalidConfiguration
+    );
+
+    for pair in curve.windows(2) {
+        require!(pair[1].threshold > pair[0].threshold, ErrorCode::InvalidConfiguration);
+    }
+    Ok(())
+}
+
+/// Time elapsed from `earlier` to `later`, in whatever unit both are given in.
+/// Rejects a clock that moved backwards instead of wrapping into a huge u64.
+pub fn seconds_between(later: i64, earlier: i64) -> Result<u64> {
+    require!(later >= earlier, ErrorCode::InvalidTimestamp);
+    let elapsed = later.checked_sub(earlier).ok_or(ErrorCode::MathOverflow)?;
+    u64::try_from(elapsed).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// Emission window check: `reward_start <= now < reward_end`.
+/// Open-ended pools report u64::MAX seconds remaining once started.
+pub fn emission_status(pool: &Pool, now: i64) -> Result<EmissionStatus> {
+    let started = now >= pool.reward_start_timestamp;
+
+    if pool.reward_end_timestamp == 0 {
+        return Ok(EmissionStatus {
+            emitting: started,
+            seconds_remaining: if started { u64::MAX } else { 0 },
+        });
+    }
+
+    if !started || now >= pool.reward_end_timestamp {
+        return Ok(EmissionStatus {
+            emitting: false,
+            seconds_remaining: 0,
+        });
+    }
+
+    Ok(EmissionStatus {
+        emitting: true,
+        seconds_remaining: seconds_between(pool.reward_end_timestamp, now)?,
+    })
+}
+
+/// The pool's lock schedule, indexed by `tier_index`.
+/// Pools without stored tiers fall back to the legacy flexible + locked pair.
+pub fn lock_tiers(pool: &Pool) -> Vec<LockTier> {
+    if pool.lock_tiers.is_empty() {
+        default_lock_tiers(pool.lock_duration, pool.lock_bonus_percentage)
+    } else {
+        pool.lock_tiers.clone()
+    }
+}
+
+/// Whether the pool stores a tier table other than the default pair for its legacy lock fields
+pub fn has_custom_lock_tiers(pool: &Pool) -> bool {
+    !pool.lock_tiers.is_empty()
+        && pool.lock_tiers != default_lock_tiers(pool.lock_duration, pool.lock_bonus_percentage)
+}
+
+/// Look up the tier a stake asked for. An index past the end of the pool's
+/// tier table is rejected rather than falling back to flexible staking.
+pub fn resolve_lock_tier(pool: &Pool, tier_index: u8) -> Result<LockTier> {
+    lock_tiers(pool)
+        .get(tier_index as usize)
+        .cloned()
+        .ok_or_else(|| error!(ErrorCode::InvalidLockType))
+}
+
+/// Flexible staking followed by a single locked tier
+pub fn default_lock_tiers(lock_duration: u64, lock_bonus_percentage: u16) -> Vec<LockTier> {
+    vec![
+        LockTier {
+            duration: 0,
+            bonus_bps: 0,
+        },
+        LockTier {
+            duration: lock_duration,
+            bonus_bps: lock_bonus_percentage,
+        },
+    ]
+}
+
+/// Lock end and bonus multiplier after adding `amount` to a locked position.
+/// The unlock time never moves earlier and covers the new tokens' full duration;
+/// the multiplier is the stake-weighted average of the old and new tokens' bonuses.
+pub fn topped_up_lock(user: &User, tier: &LockTier, amount: u64, now: i64) -> Result<(i64, u16)> {
+    let duration = i64::try_from(tier.duration).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let new_end = now.checked_add(duration).ok_or(ErrorCode::MathOverflow)?;
+    let lock_end = user.lock_end_timestamp.max(new_end);
+
+    let tier_multiplier = 10000u128
+        .checked_add(tier.bonus_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let weighted = (user.amount as u128)
+        .checked_mul(user.bonus_multiplier as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(
+            (amount as u128)
+                .checked_mul(tier_multiplier)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )
+        .ok_or(ErrorCode::MathOverflow)?;
+    let total = (user.amount as u128)
+        .checked_add(amount as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let multiplier = weighted.checked_div(total).ok_or(ErrorCode::MathOverflow)?;
+    let multiplier = u16::try_from(multiplier).map_err(|_| error!(ErrorCode::MathOverflow))?;
+
+    Ok((lock_end, multiplier))
+}
+
+/// Check a pool's stake bounds are consistent (a zero cap means unlimited)
+pub fn validate_stake_limits(min_stake: u64, max_stake_per_user: u64) -> Result<()> {
+    require!(
+        max_stake_per_user == 0 || max_stake_per_user >= min_stake,
+        ErrorCode::InvalidConfiguration
+    );
+    Ok(())
+}
+
+/// Enforce the pool's minimum deposit, per-user cap and total capacity on a
+/// stake of `amount` on top of a position currently holding `current`
+pub fn check_stake_limits(pool: &Pool, current: u64, amount: u64) -> Result<()> {
+    require!(amount >= pool.min_stake, ErrorCode::BelowMinimumStake);
+    check_stake_capacity(pool, current, amount)
+}
+
+/// Enforce the per-user cap and total capacity on `amount` more stake for a
+/// position currently holding `current`. Compounded rewards skip the minimum.
+pub fn check_stake_capacity(pool: &Pool, current: u64, amount: u64) -> Result<()> {
+    if pool.max_stake_per_user > 0 {
+        let total = current.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        require!(total <= pool.max_stake_per_user, ErrorCode::ExceedsMaxStake);
+    }
+
+    if pool.max_total_staked > 0 {
+        let total = pool.total_staked.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        require!(total <= pool.max_total_staked, ErrorCode::PoolCapacityReached);
+    }
+    Ok(())
+}
+
+/// Most stake a position holding `current` can still add under the per-user
+/// cap and pool capacity (u64::MAX when neither is set)
+pub fn stake_headroom(pool: &Pool, current: u64) -> u64 {
+    let user_room = if pool.max_stake_per_user > 0 {
+        pool.max_stake_per_user.saturating_sub(current)
+    } else {
+        u64::MAX
+    };
+    let pool_room = if pool.max_total_staked > 0 {
+        pool.max_total_staked.saturating_sub(pool.total_staked)
+    } else {
+        u64::MAX
+    };
+    user_room.min(pool_room)
+}
+
+/// Write a position's lock multiplier, refusing anything below 1x.
+/// A zero or sub-10000 multiplier would silently shrink every reward it touches.
+pub fn set_bonus_multiplier(user: &mut User, multiplier: u16) -> Result<()> {
+    require!(multiplier >= 10000, ErrorCode::InvalidConfiguration);
+    user.bonus_multiplier = multiplier;
+    Ok(())
+}
+
+/// Check a tier schedule is bounded and monotonic: one to MAX_LOCK_TIERS tiers,
+/// starting with the flexible tier 0, strictly increasing durations and
+/// non-decreasing bonuses that still fit the u16 multiplier on top of 1x
+pub fn validate_lock_tiers(tiers: &[LockTier]) -> Result<()> {
+    require!(
+        !tiers.is_empty() && tiers.len() <= MAX_LOCK_TIERS,
+        ErrorCode::InvalidConfiguration
+    );
+    require!(tiers[0].duration == 0, ErrorCode::InvalidConfiguration);
+    require!(
+        tiers.iter().all(|tier| 10000u16.checked_add(tier.bonus_bps).is_some()),
+        ErrorCode::InvalidConfiguration
+    );
+
+    for pair in tiers.windows(2) {
+        require!(pair[1].duration > pair[0].duration, ErrorCode::InvalidConfiguration);
+        require!(pair[1].bonus_bps >= pair[0].bonus_bps, ErrorCode::InvalidConfiguration);
+    }
+    Ok(())
+}
+
+/// Anyone may push an existing emission end further out, but giving an open-ended
+/// pool an end stops its emissions once the deposit runs out, so only the pool
+/// authority may do that. No instruction can make a pool open-ended again.
+pub fn check_reward_end_extension(pool: &Pool, depositor: &Pubkey) -> Result<()> {
+    require!(
+        pool.reward_end_timestamp > 0 || *depositor == pool.authority,
+        ErrorCode::Unauthorized
+    );
+    Ok(())
+}
+
+/// Emission end after funding `amount` more rewards at the pool's current rate.
+/// Extends from the later of the current end, `now` and the emission start;
+/// a zero rate leaves the end unchanged.
+pub fn extended_reward_end(pool: &Pool, amount: u64, now: i64) -> Result<i64> {
+    if pool.reward_per_second == 0 {
+        return Ok(pool.reward_end_timestamp);
+    }
+
+    let funded_seconds = i64::try_from(amount / pool.reward_per_second)
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    let base = pool.reward_end_timestamp
+        .max(now)
+        .max(pool.reward_start_timestamp);
+
+    base.checked_add(funded_seconds).ok_or(error!(ErrorCode::MathOverflow))
+}
+
+/// Convert an LST amount into underlying stake-token terms.
+/// LST is issued 1:1 against the stake token, so this is currently the identity.
+pub fn lst_to_underlying(lst_amount: u64) -> Result<u64> {
+    Ok(lst_amount)
+}
+
+/// Convert an underlying stake-token amount into LST terms (inverse of `lst_to_underlying`)
+pub fn underlying_to_lst(underlying_amount: u64) -> Result<u64> {
+    Ok(underlying_amount)
+}
+
+// ============ Account Structures ============
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + GlobalState::LEN,
+        seeds = [b"global"],
+        bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateUserAccount<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + User::LEN,
+        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: [u8; 32])]
+pub struct CreatePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Pool::LEN,
+        seeds = [b"pool", pool_id.as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, tier_index: u8)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Mint account for the stake token
+    #[account(constraint = stake_mint.key() == pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: Account<'info, Mint>,
+
+    /// Pool's stake vault, the pool PDA's associated token account (receives staked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: User's token account
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub user_token_account: Option<AccountInfo<'info>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// SPL Token program; the pool PDA signs LST mints and compounds through it
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub token_program: Option<Program<'info, Token>>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Pool's reward vault (source of auto-compounded rewards)
+    /// Optional: Only required for users in auto-compound mode
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Pool's LST mint (the pool PDA must be its mint authority)
+    /// Optional: Only required to receive LST for the deposit
+    #[account(
+        mut,
+        constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.mint_authority == COption::Some(pool.key()) @ ErrorCode::InvalidMint
+    )]
+    pub lst_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: User's LST token account (receives minted LST)
+    /// Optional: Only required together with lst_mint
+    #[account(mut)]
+    pub user_lst_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Mint account for the stake token
+    #[account(constraint = stake_mint.key() == pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: Account<'info, Mint>,
+
+    /// Pool's stake vault, the pool PDA's associated token account (holds staked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: User's token account (receives unstaked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub user_token_account: Option<AccountInfo<'info>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// SPL Token program
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub token_program: Option<Program<'info, Token>>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Pool's reward vault (receives the unstake fee, funds auto-compounding)
+    /// Optional: Only required for an unstake fee or a user in auto-compound mode
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Option<Account<'info, TokenAccount>>,
+
+    /// Pool's LST mint (the pool PDA must be its mint authority)
+    /// Optional: Only required when LST was minted against the stake
+    #[account(
+        mut,
+        constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.mint_authority == COption::Some(pool.key()) @ ErrorCode::InvalidMint
+    )]
+    pub lst_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: User's LST token account (LST is burned from here)
+    /// Optional: Only required together with lst_mint
+    #[account(mut)]
+    pub user_lst_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Mint account for the stake token
+    #[account(constraint = stake_mint.key() == pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: Account<'info, Mint>,
+
+    /// Pool's stake vault, the pool PDA's associated token account (holds staked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: User's token account (receives the principal less the penalty)
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub user_token_account: Option<AccountInfo<'info>>,
+
+    /// CHECK: Receives the penalty: the recipient's stake-token account for SPL pools,
+    /// or the recipient wallet itself for native SOL. Validated in the handler.
+    #[account(mut)]
+    pub penalty_recipient: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// SPL Token program
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub token_program: Option<Program<'info, Token>>,
+
+    /// Pool's LST mint (the pool PDA must be its mint authority)
+    /// Optional: Only required when LST was minted against the stake
+    #[account(
+        mut,
+        constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.mint_authority == COption::Some(pool.key()) @ ErrorCode::InvalidMint
+    )]
+    pub lst_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: User's LST token account (LST is burned from here)
+    /// Optional: Only required together with lst_mint
+    #[account(mut)]
+    pub user_lst_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyExit<'info> {
+    #[account(
+        seeds = [b"global"],
+        bump = global_state.bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Mint account for the stake token
+    #[account(constraint = stake_mint.key() == pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: Account<'info, Mint>,
+
+    /// Pool's stake vault, the pool PDA's associated token account (holds staked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: User's token account (receives the principal)
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub user_token_account: Option<AccountInfo<'info>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// SPL Token program
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub token_program: Option<Program<'info, Token>>,
+
+    /// Pool's LST mint (the pool PDA must be its mint authority)
+    /// Optional: Only required when LST was minted against the stake
+    #[account(
+        mut,
+        constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.mint_authority == COption::Some(pool.key()) @ ErrorCode::InvalidMint
+    )]
+    pub lst_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: User's LST token account (LST is burned from here)
+    /// Optional: Only required together with lst_mint
+    #[account(mut)]
+    pub user_lst_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct SetGlobalPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"global"],
+        bump = global_state.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolPaused<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: Account<'info, Mint>,
+
+    /// Pool-owned token account paying out rewards
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: User's reward token account (receives claimed rewards)
+    #[account(mut)]
+    pub user_reward_token_account: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+
+    /// SPL Token program; the pool PDA signs the payout through it
+    pub token_program: Program<'info, Token>,
+
+    /// Treasury token account for the protocol fee
+    /// Optional: Only required when the pool charges a reward protocol fee
+    #[account(
+        mut,
+        constraint = protocol_fee_account.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = protocol_fee_account.owner == pool.protocol_fee_recipient @ ErrorCode::InvalidTokenAccount
+    )]
+    pub protocol_fee_account: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyPairedStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(constraint = pool.paired_pool == Some(paired_pool.key()) @ ErrorCode::InvalidConfiguration)]
+    pub paired_pool: Account<'info, Pool>,
+
+    /// CHECK: Owner's position in the paired pool; may already be closed.
+    /// The address is pinned by seeds and the contents are read in the handler.
+    #[account(
+        seeds = [b"user", paired_pool.pool_id.as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub paired_user: AccountInfo<'info>,
+
+    /// CHECK: Wallet that owns both positions (read-only)
+    pub owner: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardMode<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CompoundRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: Account<'info, Mint>,
+
+    /// Pool-owned token account paying out rewards
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    /// Pool's stake vault holding the staked principal
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// SPL Token program; the pool PDA signs the compound transfers through it
+    pub token_program: Program<'info, Token>,
+
+    /// Treasury token account for the protocol fee
+    /// Optional: Only required when the pool charges a reward protocol fee
+    #[account(
+        mut,
+        constraint = protocol_fee_account.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = protocol_fee_account.owner == pool.protocol_fee_recipient @ ErrorCode::InvalidTokenAccount
+    )]
+    pub protocol_fee_account: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserAccount<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        close = user_wallet,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: User wallet, must be the position owner (receives rent and native SOL principal)
+    #[account(
+        mut,
+        constraint = user_wallet.key() == authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_wallet: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: Mint account for the stake token
+    #[account(constraint = stake_mint.key() == pool.stake_mint @ ErrorCode::InvalidMint)]
+    pub stake_mint: Account<'info, Mint>,
+
+    /// Pool's stake vault, the pool PDA's associated token account (holds staked SPL tokens)
+    /// Optional: Only required for SPL tokens, not native SOL
+    #[account(
+        mut,
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: User's token account (receives the remaining principal)
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub user_token_account: Option<AccountInfo<'info>>,
+
+    /// SPL Token program
+    /// Optional: Only required for SPL tokens, not native SOL
+    pub token_program: Option<Program<'info, Token>>,
+
+    /// Pool's LST mint (the pool PDA must be its mint authority)
+    /// Optional: Only required when LST was minted against the stake
+    #[account(
+        mut,
+        constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint,
+        constraint = lst_mint.mint_authority == COption::Some(pool.key()) @ ErrorCode::InvalidMint
+    )]
+    pub lst_mint: Option<Account<'info, Mint>>,
+
+    /// CHECK: User's LST token account (LST is burned from here)
+    /// Optional: Only required together with lst_mint
+    #[account(mut)]
+    pub user_lst_account: Option<AccountInfo<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct PokePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserToAccumulator<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// CHECK: The owner's position, in the legacy or current layout; decoded by the handler
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK: Wallet that owns the position (read-only)
+    pub owner: AccountInfo<'info>,
+
+    /// Pays the rent for the larger account
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: [u8; 32])]
+pub struct MigratePool<'info> {
+    /// CHECK: Pool in the legacy layout; decoded and checked against the signer by the handler
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub pool: UncheckedAccount<'info>,
+
+    /// Pool authority; pays the rent for the larger account and, for native SOL pools,
+    /// returns the staked lamports it held under the legacy layout
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Mint account for the stake token
+    /// Optional: Only required for SPL pools with staked principal
+    pub stake_mint: Option<Account<'info, Mint>>,
+
+    /// Authority's stake-token account, which held the principal under the legacy layout
+    /// Optional: Only required for SPL pools with staked principal
+    #[account(mut)]
+    pub authority_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Pool's stake vault (receives the principal); checked against the pool by the handler
+    /// Optional: Only required for SPL pools with staked principal
+    #[account(mut)]
+    pub stake_vault: Option<Account<'info, TokenAccount>>,
+
+    /// SPL Token program
+    /// Optional: Only required for SPL pools with staked principal
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileTotalStaked<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    /// Pool's stake vault holding the staked principal
+    #[account(
+        constraint = stake_vault.key() == get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub stake_vault: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PokePoolsBatch<'info> {
+    /// Keeper submitting the batch; any signer may crank
+    pub keeper: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct StakeLst<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: Account<'info, Mint>,
+
+    /// Pool's LST vault, the pool PDA's associated token account (receives restaked LST)
+    #[account(
+        mut,
+        constraint = lst_vault.key() == get_associated_token_address(&pool.key(), &pool.lst_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub lst_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: User's LST token account
+    #[account(mut)]
+    pub user_lst_account: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+
+    /// SPL Token program
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeLst<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        mut,
+        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(constraint = lst_mint.key() == pool.lst_mint @ ErrorCode::InvalidMint)]
+    pub lst_mint: Account<'info, Mint>,
+
+    /// Pool's LST vault (holds restaked LST; the pool PDA signs redemptions)
+    #[account(
+        mut,
+        constraint = lst_vault.key() == get_associated_token_address(&pool.key(), &pool.lst_mint) @ ErrorCode::InvalidStakeVault
+    )]
+    pub lst_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: User's LST token account (receives redeemed LST)
+    #[account(mut)]
+    pub user_lst_account: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+
+    /// SPL Token program
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DepositRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: Account<'info, Mint>,
+
+    /// Reward vault: a reward_mint token account owned by the pool PDA
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    /// CHECK: Depositor's reward token account
+    #[account(mut)]
+    pub depositor_token_account: AccountInfo<'info>,
+
+    pub depositor: Signer<'info>,
+
+    /// CHECK: Token program or Token-2022 program
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewardVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(constraint = reward_mint.key() == pool.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: Account<'info, Mint>,
+
+    /// Reward vault: a reward_mint token account owned by the pool PDA
+    #[account(
+        mut,
+        constraint = reward_vault.mint == pool.reward_mint @ ErrorCode::InvalidMint,
+        constraint = reward_vault.owner == pool.key() @ ErrorCode::InvalidRewardVault,
+        constraint = reward_vault.key() != get_associated_token_address(&pool.key(), &pool.stake_mint) @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    /// Funder's reward token account
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == pool.reward_mint @ ErrorCode::InvalidMint
+    )]
+    pub funder_token_account: Account<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+
+    /// SPL Token program
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct SweepForeignTokens<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(constraint = foreign_mint.key() == mint @ ErrorCode::InvalidMint)]
+    pub foreign_mint: Account<'info, Mint>,
+
+    /// Pool-owned token account holding the stray tokens
+    #[account(
+        mut,
+        constraint = pool_token_account.mint == mint @ ErrorCode::InvalidMint,
+        constraint = pool_token_account.owner == pool.key() @ ErrorCode::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+
+    /// Authority's token account (receives the swept tokens)
+    #[account(
+        mut,
+        constraint = destination_token_account.mint == mint @ ErrorCode::InvalidMint,
+        constraint = destination_token_account.owner == pool.authority @ ErrorCode::InvalidTokenAccount
+    )]
+    pub destination_token_account: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// SPL Token program; the pool PDA signs the sweep through it
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ViewPool<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct ViewUser<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        seeds = [b"user", pool.pool_id.as_ref(), owner.key().as_ref()],
+        bump = user.bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// CHECK: Wallet that owns the position (read-only)
+    pub owner: AccountInfo<'info>,
+}
+
+// ============ Data Structures ============
+
+#[account]
+pub struct GlobalState {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub pool_count: u64,
+    pub paused: bool, // Emergency mode: users may exit regardless of locks
+}
+
+impl GlobalState {
+    pub const LEN: usize = 8 + 32 + 8 + 1; // bump + authority + pool_count + paused
+}
+
+#[account]
+#[derive(Default)]
+pub struct Pool {
+    pub bump: u8,
+    pub pool_id: [u8; 32],          // Pool identifier (e.g., "wave", "wealth")
+    pub stake_mint: Pubkey,         // Token being staked
+    pub lst_mint: Pubkey,           // Liquid Staking Token mint
+    pub reward_mint: Pubkey,        // Reward token mint
+    pub reward_per_second: u64,     // Base reward rate
+    pub lock_duration: u64,         // Lock duration in seconds (2592000 = 30 days)
+    pub lock_bonus_percentage: u16, // Bonus percentage (5000 = 50%)
+    pub total_staked: u64,          // Total tokens staked in pool
+    pub total_reward_distributed: u64, // Total rewards distributed
+    pub last_update_timestamp: i64, // Last time pool was updated
+    pub authority: Pubkey,          // Pool authority
+    pub total_lst_staked: u64,      // Restaked LST (in underlying terms)
+    pub tenure_bonus_bps: u16,      // Flexible loyalty bonus per tenure period
+    pub tenure_period: u64,         // Tenure period in seconds (0 = disabled)
+    pub tenure_bonus_cap_bps: u16,  // Maximum loyalty bonus
+    pub reward_start_timestamp: i64, // Emissions accrue from this time onward
+    pub post_lock_grace: u64,       // Seconds the lock bonus persists after lock end
+    pub reward_reserve: u64,        // Reward tokens deposited and not yet paid out
+    pub last_update_millis: i64,    // Last update at sub-second resolution
+    pub clock_anchor_timestamp: i64, // Second of the first slot seen in that second
+    pub clock_anchor_slot: u64,     // First slot seen in clock_anchor_timestamp
+    pub locked: bool,               // Reentrancy guard, only set mid-instruction
+    pub max_reward_per_user: u64,   // Lifetime reward cap per user (0 = uncapped)
+    pub reward_end_timestamp: i64,  // Emissions stop at this time (0 = open-ended)
+    pub reward_protocol_fee_bps: u16, // Protocol cut of claimed rewards
+    pub protocol_fee_recipient: Pubkey, // Treasury receiving the protocol cut
+    pub unstake_fee_bps: u16,       // Fee on unstaked principal, paid into the reward reserve
+    pub total_reward_funded: u64,   // Lifetime reward tokens deposited into the vault
+    pub checkpoint_slot: u64,       // Slot of the last total_staked checkpoint
+    pub checkpoint_total_staked: u64, // Reward-bearing stake (with restaked LST) at the start of checkpoint_slot
+    pub early_unstake_penalty_bps: u16, // Penalty for leaving a lock before it expires
+    pub reward_per_token_stored: u128, // Accumulated reward per staked token (x REWARD_PRECISION)
+    pub is_paused: bool,            // Blocks new stakes; exits and claims stay open
+    pub lock_tiers: Vec<LockTier>,  // Lock options offered to stakers (up to MAX_LOCK_TIERS)
+    pub amount_multiplier_curve: Vec<AmountMultiplier>, // Stake-size reward scaling (empty = 1x)
+    pub paired_pool: Option<Pubkey>, // Pool whose stakers can earn the pair bonus here
+    pub pair_bonus_bps: u16,        // Bonus for stakers verified in the paired pool
+    pub staker_count: u64,          // Positions currently holding a nonzero stake
+    pub min_stake: u64,             // Smallest amount a single stake may deposit
+    pub max_stake_per_user: u64,    // Cap on one position's stake (0 = unlimited)
+    pub max_total_staked: u64,      // Pool capacity across all positions (0 = uncapped)
+}
+
+impl Pool {
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool_id
+        32 + // stake_mint
+        32 + // lst_mint
+        32 + // reward_mint
+        8 +  // reward_per_second
+        8 +  // lock_duration
+        2 +  // lock_bonus_percentage
+        8 +  // total_staked
+        8 +  // total_reward_distributed
+        8 +  // last_update_timestamp
+        32 + // authority
+        8 +  // total_lst_staked
+        2 +  // tenure_bonus_bps
+        8 +  // tenure_period
+        2 +  // tenure_bonus_cap_bps
+        8 +  // reward_start_timestamp
+        8 +  // post_lock_grace
+        8 +  // reward_reserve
+        8 +  // last_update_millis
+        8 +  // clock_anchor_timestamp
+        8 +  // clock_anchor_slot
+        1 +  // locked
+        8 +  // max_reward_per_user
+        8 +  // reward_end_timestamp
+        2 +  // reward_protocol_fee_bps
+        32 + // protocol_fee_recipient
+        2 +  // unstake_fee_bps
+        8 +  // total_reward_funded
+        8 +  // checkpoint_slot
+        8 +  // checkpoint_total_staked
+        2 +  // early_unstake_penalty_bps
+        16 + // reward_per_token_stored
+        1 +  // is_paused
+        4 + MAX_LOCK_TIERS * LockTier::LEN + // lock_tiers
+        4 + MAX_AMOUNT_CURVE_POINTS * AmountMultiplier::LEN + // amount_multiplier_curve
+        1 + 32 + // paired_pool
+        2 +  // pair_bonus_bps
+        8 +  // staker_count
+        8 +  // min_stake
+        8 +  // max_stake_per_user
+        8;   // max_total_staked
+}
+
+#[account]
+pub struct User {
+    pub bump: u8,
+    pub amount: u64,                  // Amount staked
+    pub lock_type: u8,                // 0 = flexible, 1 = locked
+    pub lock_start_timestamp: i64,    // Lock start time
+    pub lock_end_timestamp: i64,      // Lock end time
+    pub bonus_multiplier: u16,        // Reward multiplier (10000 = 1x)
+    pub last_reward_claim_timestamp: i64, // Last reward claim
+    pub lst_staked_underlying: u64,   // Restaked LST position (in underlying terms)
+    pub tier_index: u8,               // Lock tier chosen at stake time (0 = flexible)
+    pub continuous_stake_start: i64,  // Start of current uninterrupted stake (0 = none)
+    pub last_reward_claim_millis: i64, // Last reward claim at sub-second resolution
+    pub cumulative_rewards: u64,      // Total rewards claimed from this pool
+    pub rewards_earned: u64,          // Rewards banked at settlement or left by a partial claim
+    pub reward_per_token_paid: u128,  // Pool accumulator value at the last settlement
+    pub reward_mode: u8,              // 0 = accumulate, 1 = auto-compound on stake/unstake
+    pub lst_minted: u64,              // LST issued against this stake, burned on unstake
+    pub verified_paired_pool: Pubkey, // Paired pool the owner was last verified in (default = none)
+    pub accumulator_migrated: bool,   // Checkpointed against the reward accumulator
+    pub verified_paired_user: Pubkey, // Paired position checked at verification, re-read on settlement
+    pub pair_bonus_bps: u16,          // Pair bonus granted at verification (0 = none)
+}
+
+impl Default for User {
+    // An empty position earns at 1x, never 0x
+    fn default() -> Self {
+        Self {
+            bump: 0,
+            amount: 0,
+            lock_type: 0,
+            lock_start_timestamp: 0,
+            lock_end_timestamp: 0,
+            bonus_multiplier: 10000,
+            last_reward_claim_timestamp: 0,
+            lst_staked_underlying: 0,
+            tier_index: 0,
+            continuous_stake_start: 0,
+            last_reward_claim_millis: 0,
+            cumulative_rewards: 0,
+            rewards_earned: 0,
+            reward_per_token_paid: 0,
+            reward_mode: 0,
+            lst_minted: 0,
+            verified_paired_pool: Pubkey::default(),
+            accumulator_migrated: false,
+            verified_paired_user: Pubkey::default(),
+            pair_bonus_bps: 0,
+        }
+    }
+}
+
+impl User {
+    pub const LEN: usize = 8 + // discriminator
+        1 + // bump
+        8 + // amount
+        1 + // lock_type
+        8 + // lock_start_timestamp
+        8 + // lock_end_timestamp
+        2 + // bonus_multiplier
+        8 + // last_reward_claim_timestamp
+        8 + // lst_staked_underlying
+        1 + // tier_index
+        8 + // continuous_stake_start
+        8 + // last_reward_claim_millis
+        8 + // cumulative_rewards
+        8 + // rewards_earned
+        16 + // reward_per_token_paid
+        1 + // reward_mode
+        8 + // lst_minted
+        32 + // verified_paired_pool
+        1 + // accumulator_migrated
+        32 + // verified_paired_user
+        2;  // pair_bonus_bps
+}
+
+/// `Pool` as written by the first program release
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct LegacyPool {
+    pub bump: u8,
+    pub pool_id: [u8; 32],
+    pub stake_mint: Pubkey,
+    pub lst_mint: Pubkey,
+    pub reward_mint: Pubkey,
+    pub reward_per_second: u64,
+    pub lock_duration: u64,
+    pub lock_bonus_percentage: u16,
+    pub total_staked: u64,
+    pub total_reward_distributed: u64,
+    pub last_update_timestamp: i64,
+    pub authority: Pubkey,
+}
+
+/// `User` as written by the first program release
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct LegacyUser {
+    pub bump: u8,
+    pub amount: u64,
+    pub lock_type: u8,
+    pub lock_start_timestamp: i64,
+    pub lock_end_timestamp: i64,
+    pub bonus_multiplier: u16,
+    pub last_reward_claim_timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LockTier {
+    pub duration: u64,  // Lock duration in seconds (0 = flexible)
+    pub bonus_bps: u16, // Bonus on top of 1x (5000 = 50%)
+}
+
+impl LockTier {
+    pub const LEN: usize = 8 + 2; // duration + bonus_bps
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AmountMultiplier {
+    pub threshold: u64,      // Minimum stake for this point to apply
+    pub multiplier_bps: u16, // Reward weight for stakes at or above threshold (10000 = 1x)
+}
+
+impl AmountMultiplier {
+    pub const LEN: usize = 8 + 2; // threshold + multiplier_bps
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EmissionStatus {
+    pub emitting: bool,          // Inside the emission window right now
+    pub seconds_remaining: u64,  // Until the window closes (u64::MAX = open-ended)
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UserPosition {
+    pub amount: u64,              // Tokens staked
+    pub lock_type: u8,            // 0 = flexible, 1 = locked
+    pub lock_end_timestamp: i64,  // When the lock expires (0 for flexible)
+    pub multiplier_bps: u64,      // Current reward multiplier (10000 = 1x)
+    pub pair_bonus_bps: u64,      // Paired-pool bonus on top of it (0 = none)
+    pub pending_rewards: u64,     // Accrued rewards before caps
+    pub claimable_rewards: u64,   // Pending rewards capped by user limit and reserve
+}
+
+// ============ Events ============
+
+#[event]
+pub struct Staked {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub lock_type: u8,
+    pub tier_index: u8,
+    pub memo: Option<[u8; 32]>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsDeposited {
+    pub pool: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub reward_reserve: u64,
+    pub total_reward_funded: u64,
+    pub reward_end_timestamp: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardVaultFunded {
+    pub pool: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RewardReserveUpdated {
+    pub pool: Pubkey,
+    pub updated_by: Pubkey,
+    pub deposited: u64,
+    pub withdrawn: u64,
+    pub reward_reserve: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub protocol_fee: u64,
+    pub protocol_fee_recipient: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Reconciled {
+    pub pool: Pubkey,
+    pub before: u64,
+    pub after: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsCompounded {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub protocol_fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PoolPauseToggled {
+    pub pool: Pubkey,
+    pub paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EarlyUnstake {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub penalty: u64,
+    pub timestamp: i64,
+}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + GlobalState::LEN,
-        seeds = [b"global"],
-        bump
-    )]
-    pub global_state: Account<'info, GlobalState>,
+// ============ Error Codes ============
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Invalid amount provided")]
+    InvalidAmount,
+    #[msg("Insufficient staked amount")]
+    InsufficientStake,
+    #[msg("Still in lock period")]
+    StillInLockPeriod,
+    #[msg("Math overflow occurred")]
+    MathOverflow,
+    #[msg("No rewards available to claim")]
+    NoRewardsAvailable,
+    #[msg("Invalid token mint")]
+    InvalidMint,
+    #[msg("Invalid token program")]
+    InvalidTokenProgram,
+    #[msg("Timestamp is earlier than the last recorded update")]
+    InvalidTimestamp,
+    #[msg("Invalid configuration")]
+    InvalidConfiguration,
+    #[msg("Reward vault is not owned by the pool")]
+    InvalidRewardVault,
+    #[msg("Re-entrant call into a pool that is mid-instruction")]
+    Reentrancy,
+    #[msg("Signer is not the pool authority")]
+    Unauthorized,
+    #[msg("Invalid token account")]
+    InvalidTokenAccount,
+    #[msg("Emergency exit is only available while the program is paused")]
+    NotInEmergency,
+    #[msg("Reward vault balance is too low to pay these rewards")]
+    InsufficientLiquidity,
+    #[msg("Stake is not in an active lock period")]
+    NotInLockPeriod,
+    /// No longer returned: unknown tiers fail with InvalidLockType. Kept so the
+    /// codes of later variants don't shift.
+    #[msg("Lock tier is not defined for this pool")]
+    InvalidLockTier,
+    #[msg("Pool is paused")]
+    PoolPaused,
+    #[msg("Rewards can only be compounded when they are paid in the stake token")]
+    CannotCompoundDifferentMint,
+    #[msg("Stake amount is below the pool minimum")]
+    BelowMinimumStake,
+    #[msg("Stake would exceed the pool's per-user maximum")]
+    ExceedsMaxStake,
+    #[msg("Pool is at capacity")]
+    PoolCapacityReached,
+    #[msg("User has already been migrated")]
+    AlreadyMigrated,
+    #[msg("Lock type is not defined for this pool")]
+    InvalidLockType,
+    #[msg("Top-ups must use the position's existing lock tier")]
+    LockTierMismatch,
+    #[msg("Claim pending rewards before closing the account")]
+    UnclaimedRewards,
+    #[msg("Redeem restaked LST before closing the account")]
+    LstStillStaked,
+    #[msg("Stake vault is not the pool's associated token account")]
+    InvalidStakeVault,
+    #[msg("Pool does not hold enough lamports to release this amount above rent exemption")]
+    InsufficientPoolLamports,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(seconds: i64) -> i64 {
+        seconds * 1000
+    }
+
+    #[test]
+    fn controlled_clock_drives_stake_accrue_claim() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1_000,
+            reward_reserve: 1_000_000,
+            ..Default::default()
+        };
+        let mut user = User::default();
+
+        let tick = |pool: &mut Pool, unix_timestamp: i64, slot: u64| {
+            set_test_clock(unix_timestamp, slot);
+            let now_ms = touch_clock(pool, &current_clock().unwrap()).unwrap();
+            update_pool_rewards(pool, now_ms).unwrap();
+            now_ms
+        };
+
+        // Stake: settle first, then grow the position
+        let now_ms = tick(&mut pool, start, 100);
+        settle_user_rewards(&pool, &mut user, now_ms).unwrap();
+        user.amount = 1_000;
+        pool.total_staked = 1_000;
+        track_staker_transition(&mut pool, 0, user.amount).unwrap();
+
+        // Accrue: ten seconds as the only staker earns the full emission
+        let now_ms = tick(&mut pool, start + 10, 125);
+        assert_eq!(compute_claimable_rewards(&pool, &user, now_ms).unwrap(), 10_000);
+
+        // A later slot in the same second advances sub-second time
+        let now_ms = tick(&mut pool, start + 10, 126);
+        let expected = 10_000 + MILLIS_PER_SLOT;
+        assert_eq!(compute_claimable_rewards(&pool, &user, now_ms).unwrap(), expected);
+
+        // Claim: bank and pay out; nothing more is owed until time moves
+        settle_user_rewards(&pool, &mut user, now_ms).unwrap();
+        let claimed = std::mem::take(&mut user.rewards_earned);
+        assert_eq!(claimed, expected);
+        assert_eq!(compute_pending_rewards(&pool, &user, now_ms).unwrap(), 0);
+
+        let now_ms = tick(&mut pool, start + 11, 128);
+        assert!(compute_pending_rewards(&pool, &user, now_ms).unwrap() > 0);
+    }
+
+    #[test]
+    fn migrated_legacy_user_gets_no_retroactive_rewards() {
+        let now = 1_700_000_000;
+        let pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 1_000_000,
+            last_update_millis: ms(now),
+            reward_per_token_stored: 5 * REWARD_PRECISION,
+            ..Default::default()
+        };
+        let mut legacy = User {
+            amount: 10_000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
+
+        // Unmigrated, a zero checkpoint is paid the accumulator's whole history
+        assert_eq!(compute_pending_rewards(&pool, &legacy, ms(now)).unwrap(), 50_000);
+
+        migrate_legacy_user(&pool, &mut legacy, ms(now)).unwrap();
+        assert!(legacy.accumulator_migrated);
+        assert_eq!(compute_pending_rewards(&pool, &legacy, ms(now)).unwrap(), 0);
+        assert!(migrate_legacy_user(&pool, &mut legacy, ms(now)).is_err());
+
+        // Afterwards it earns its share like any other staker
+        assert_eq!(compute_pending_rewards(&pool, &legacy, ms(now + 10)).unwrap(), 100);
+    }
+
+    #[test]
+    fn seconds_between_equal_is_zero() {
+        assert_eq!(seconds_between(1_700_000_000, 1_700_000_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn seconds_between_increasing() {
+        assert_eq!(seconds_between(1_700_000_060, 1_700_000_000).unwrap(), 60);
+        assert_eq!(seconds_between(i64::MAX, 0).unwrap(), i64::MAX as u64);
+    }
+
+    #[test]
+    fn seconds_between_decreasing_is_rejected() {
+        assert_eq!(
+            seconds_between(1_700_000_000, 1_700_000_001).unwrap_err(),
+            error!(ErrorCode::InvalidTimestamp)
+        );
+        assert!(seconds_between(i64::MIN, 0).is_err());
+    }
+
+    #[test]
+    fn sole_staker_earns_the_full_emission() {
+        let now = 1_700_000_000;
+        let hour = 3_600;
+        let pool = Pool {
+            reward_per_second: 1_000_000,
+            total_staked: 100_000_000,
+            last_update_millis: ms(now - hour),
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100_000_000,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - hour),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            compute_pending_rewards(&pool, &user, ms(now)).unwrap(),
+            3_600_000_000
+        );
+
+        // A 1.5x lock bonus scales the payout once, not by 10000 again
+        user.bonus_multiplier = 15000;
+        assert_eq!(
+            compute_pending_rewards(&pool, &user, ms(now)).unwrap(),
+            5_400_000_000
+        );
+    }
+
+    #[test]
+    fn large_stake_over_a_year_does_not_overflow() {
+        let now = 1_700_000_000;
+        let year = 31_536_000;
+        // Every staked token has earned one reward token since the last settlement
+        let pool = Pool {
+            total_staked: 10_000_000_000_000_000,
+            reward_per_token_stored: REWARD_PRECISION,
+            last_update_millis: ms(now),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 10_000_000_000_000_000,
+            lock_type: 1,
+            lock_end_timestamp: now + year,
+            bonus_multiplier: 15000,
+            last_reward_claim_millis: ms(now - year),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            compute_pending_rewards(&pool, &user, ms(now)).unwrap(),
+            15_000_000_000_000_000
+        );
+    }
+
+    #[test]
+    fn long_tenure_flexible_staker_earns_more() {
+        let now = 1_700_000_000;
+        let day = 86_400;
+        let pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 200,
+            tenure_bonus_bps: 100,
+            tenure_period: 30 * day as u64,
+            tenure_bonus_cap_bps: 500,
+            ..Default::default()
+        };
+        let fresh = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 60),
+            continuous_stake_start: now - 60,
+            ..Default::default()
+        };
+        let veteran = User {
+            continuous_stake_start: now - 90 * day,
+            ..fresh.clone()
+        };
+
+        assert_eq!(tenure_bonus_bps(&pool, &fresh, now).unwrap(), 0);
+        assert_eq!(tenure_bonus_bps(&pool, &veteran, now).unwrap(), 300);
+        assert!(
+            compute_pending_rewards(&pool, &veteran, ms(now)).unwrap()
+                > compute_pending_rewards(&pool, &fresh, ms(now)).unwrap()
+        );
+
+        let ancient = User {
+            continuous_stake_start: now - 3_650 * day,
+            ..fresh.clone()
+        };
+        assert_eq!(tenure_bonus_bps(&pool, &ancient, now).unwrap(), 500);
+    }
+
+    #[test]
+    fn late_settlement_earns_tenure_bonus_only_once_reached() {
+        let start = 1_700_000_000;
+        let pool = Pool {
+            tenure_bonus_bps: 100,
+            tenure_period: 100,
+            tenure_bonus_cap_bps: 300,
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            continuous_stake_start: start,
+            ..Default::default()
+        };
+
+        // 100s each at +0, +1% and +2%, then 700s at the 3% cap
+        let once = weighted_accrual_millis(&pool, &user, ms(start), ms(start + 1_000)).unwrap();
+        assert_eq!(once, 1_000_000 * 10000 + 100_000 * (100 + 200) + 700_000 * 300);
+        assert!(once < 1_000_000 * (10000 + 300));
+
+        // Settling in between doesn't change the total
+        let split = weighted_accrual_millis(&pool, &user, ms(start), ms(start + 150)).unwrap()
+            + weighted_accrual_millis(&pool, &user, ms(start + 150), ms(start + 1_000)).unwrap();
+        assert_eq!(split, once);
+    }
+
+    #[test]
+    fn tenure_bonus_change_reprices_the_unsettled_interval() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 10,
+            total_staked: 100,
+            last_update_millis: ms(start),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(start),
+            continuous_stake_start: start - 1_000,
+            ..Default::default()
+        };
+
+        // 100s accrued without a tenure bonus, then one is switched on before the user settles
+        let later = ms(start + 100);
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 1_000);
+        pool.tenure_bonus_bps = 100;
+        pool.tenure_period = 100;
+        pool.tenure_bonus_cap_bps = 500;
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 1_050);
+    }
+
+    #[test]
+    fn no_rewards_accrue_before_emission_start() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 100,
+            last_update_millis: ms(now - 100),
+            reward_start_timestamp: now + 3_600,
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
+
+        assert_eq!(compute_pending_rewards(&pool, &user, ms(now)).unwrap(), 0);
+        update_pool_rewards(&mut pool, ms(now)).unwrap();
+        assert_eq!(pool.total_reward_distributed, 0);
+
+        // Only the 10 seconds after the start count
+        let later = now + 3_610;
+        assert_eq!(accrual_millis(&pool, user.last_reward_claim_millis, ms(later)).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn lock_bonus_persists_through_grace_then_reverts() {
+        let lock_end = 1_700_000_000;
+        let pool = Pool {
+            reward_per_second: 1,
+            total_staked: 100,
+            post_lock_grace: 100,
+            last_update_millis: ms(lock_end - 100),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            lock_type: 1,
+            lock_end_timestamp: lock_end,
+            bonus_multiplier: 15000,
+            last_reward_claim_millis: ms(lock_end - 100),
+            ..Default::default()
+        };
+
+        assert_eq!(effective_multiplier(&pool, &user, lock_end - 1).unwrap(), 15000);
+        assert_eq!(effective_multiplier(&pool, &user, lock_end + 50).unwrap(), 15000);
+        assert_eq!(effective_multiplier(&pool, &user, lock_end + 100).unwrap(), 10000);
+
+        // 200 boosted seconds (lock + grace) then 100 seconds at 1x
+        let boosted_only = compute_pending_rewards(&pool, &user, ms(lock_end + 100)).unwrap();
+        let with_tail = compute_pending_rewards(&pool, &user, ms(lock_end + 200)).unwrap();
+        assert_eq!(boosted_only, 200 * 15000 / 10000);
+        assert_eq!(with_tail - boosted_only, 100);
+    }
+
+    #[test]
+    fn grace_change_reprices_the_unsettled_interval() {
+        let lock_end = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1,
+            total_staked: 100,
+            last_update_millis: ms(lock_end - 100),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            lock_type: 1,
+            lock_end_timestamp: lock_end,
+            bonus_multiplier: 15000,
+            last_reward_claim_millis: ms(lock_end - 100),
+            ..Default::default()
+        };
+
+        // 100 boosted seconds then 100 at 1x, until a grace window covers them after the fact
+        let later = ms(lock_end + 100);
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 150 + 100);
+        pool.post_lock_grace = 100;
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 300);
+    }
+
+    #[test]
+    fn claimable_is_capped_by_reserve() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1,
+            total_staked: 100,
+            reward_reserve: 5,
+            last_update_millis: ms(now - 10),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 10),
+            ..Default::default()
+        };
+
+        let pending = compute_pending_rewards(&pool, &user, ms(now)).unwrap();
+        assert!(pending > pool.reward_reserve);
+        assert_eq!(compute_claimable_rewards(&pool, &user, ms(now)).unwrap(), 5);
+
+        pool.reward_reserve = u64::MAX;
+        assert_eq!(compute_claimable_rewards(&pool, &user, ms(now)).unwrap(), pending);
+    }
+
+    #[test]
+    fn same_second_interactions_accrue_by_slot() {
+        let now = 1_700_000_000;
+        let clock_at = |slot: u64| Clock {
+            slot,
+            unix_timestamp: now,
+            ..Default::default()
+        };
+        let mut pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 100,
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            ..Default::default()
+        };
+
+        // First interaction in this second anchors the sub-second clock
+        let anchor_ms = touch_clock(&mut pool, &clock_at(100)).unwrap();
+        update_pool_rewards(&mut pool, anchor_ms).unwrap();
+        user.last_reward_claim_millis = anchor_ms;
+        user.reward_per_token_paid = pool.reward_per_token_stored;
+
+        for slot in [101, 102] {
+            let now_ms = touch_clock(&mut pool, &clock_at(slot)).unwrap();
+            update_pool_rewards(&mut pool, now_ms).unwrap();
+            settle_user_rewards(&pool, &mut user, now_ms).unwrap();
+        }
+
+        // Two slots of ~400ms each at 1000/s for the whole pool
+        assert_eq!(user.last_reward_claim_millis, ms(now) + 800);
+        assert_eq!(user.rewards_earned, 800);
+
+        // The estimate never spills into the next second
+        assert_eq!(current_millis(&pool, &clock_at(200)).unwrap(), ms(now) + 999);
+    }
+
+    #[test]
+    fn rate_change_mid_period_pays_each_rate_for_its_span() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 10,
+            total_staked: 100,
+            last_update_millis: ms(start),
+            ..Default::default()
+        };
+        let alice = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(start),
+            ..Default::default()
+        };
+
+        // 100s at 10/s, then the admin doubles the rate
+        update_pool_rewards(&mut pool, ms(start + 100)).unwrap();
+        pool.reward_per_second = 20;
+
+        // A second staker joins and halves everyone's share from here on
+        let mut bob = alice.clone();
+        settle_user_rewards(&pool, &mut bob, ms(start + 100)).unwrap();
+        bob.rewards_earned = 0;
+        pool.total_staked += bob.amount;
+
+        // Alice: 1000 alone at the old rate + half of 100s at 20/s
+        let later = ms(start + 200);
+        assert_eq!(compute_pending_rewards(&pool, &alice, later).unwrap(), 1_000 + 1_000);
+        assert_eq!(compute_pending_rewards(&pool, &bob, later).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn restaked_lst_earns_alongside_principal() {
+        let start = 1_700_000_000;
+        let pool = Pool {
+            reward_per_second: 10,
+            total_staked: 100,
+            total_lst_staked: 100,
+            last_update_millis: ms(start),
+            ..Default::default()
+        };
+        let staker = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(start),
+            ..Default::default()
+        };
+        let restaker = User {
+            amount: 0,
+            lst_staked_underlying: 100,
+            ..staker.clone()
+        };
+
+        // Principal and restaked LST split the pool's emissions evenly
+        assert_eq!(staked_for_rewards(&pool), 200);
+        let later = ms(start + 100);
+        assert_eq!(compute_pending_rewards(&pool, &staker, later).unwrap(), 500);
+        assert_eq!(compute_pending_rewards(&pool, &restaker, later).unwrap(), 500);
+    }
+
+    #[test]
+    fn whale_stops_accruing_at_per_user_cap() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1,
+            total_staked: 1_000,
+            max_reward_per_user: 50,
+            last_update_millis: ms(now - 100),
+            ..Default::default()
+        };
+        let mut whale = User {
+            amount: 900,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
+        let minnow = User {
+            amount: 100,
+            ..whale.clone()
+        };
+
+        // The whale's first claim is clipped to the cap
+        let pending = compute_pending_rewards(&pool, &whale, ms(now)).unwrap();
+        assert!(pending > pool.max_reward_per_user);
+        let paid = cap_user_rewards(&pool, &whale, pending).unwrap();
+        assert_eq!(paid, pool.max_reward_per_user);
+        update_pool_rewards(&mut pool, ms(now)).unwrap();
+        whale.cumulative_rewards += paid;
+        whale.last_reward_claim_millis = ms(now);
+        whale.reward_per_token_paid = pool.reward_per_token_stored;
+
+        // Further accrual earns the whale nothing, while the small staker keeps earning
+        let later = now + 100;
+        let whale_pending = compute_pending_rewards(&pool, &whale, ms(later)).unwrap();
+        assert!(whale_pending > 0);
+        assert_eq!(cap_user_rewards(&pool, &whale, whale_pending).unwrap(), 0);
+
+        let minnow_pending = compute_pending_rewards(&pool, &minnow, ms(later)).unwrap();
+        assert!(minnow_pending < pool.max_reward_per_user);
+        assert_eq!(cap_user_rewards(&pool, &minnow, minnow_pending).unwrap(), minnow_pending);
+    }
+
+    #[test]
+    fn installments_extend_reward_end() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 10,
+            reward_start_timestamp: now,
+            ..Default::default()
+        };
+
+        // Open-ended pools start the funded window from now
+        pool.reward_end_timestamp = extended_reward_end(&pool, 1_000, now).unwrap();
+        assert_eq!(pool.reward_end_timestamp, now + 100);
+
+        // A second installment stacks on the existing end
+        pool.reward_end_timestamp = extended_reward_end(&pool, 500, now + 10).unwrap();
+        assert_eq!(pool.reward_end_timestamp, now + 150);
+
+        // A lapsed schedule restarts from the deposit time
+        assert_eq!(extended_reward_end(&pool, 500, now + 1_000).unwrap(), now + 1_050);
+
+        pool.reward_per_second = 0;
+        assert_eq!(extended_reward_end(&pool, 500, now).unwrap(), now + 150);
+    }
+
+    #[test]
+    fn only_the_authority_can_end_an_open_ended_pool() {
+        let authority = Pubkey::new_unique();
+        let depositor = Pubkey::new_unique();
+        let mut pool = Pool {
+            authority,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            check_reward_end_extension(&pool, &depositor).unwrap_err(),
+            error!(ErrorCode::Unauthorized)
+        );
+        assert!(check_reward_end_extension(&pool, &authority).is_ok());
+
+        // Once an end exists, any depositor can fund it further
+        pool.reward_end_timestamp = 1_700_000_100;
+        assert!(check_reward_end_extension(&pool, &depositor).is_ok());
+    }
+
+    #[test]
+    fn legacy_user_accounts_decode_and_migrate() {
+        let legacy = LegacyUser {
+            bump: 254,
+            amount: 5_000,
+            lock_type: 1,
+            lock_start_timestamp: 1_600_000_000,
+            lock_end_timestamp: 1_700_000_000,
+            bonus_multiplier: 15000,
+            last_reward_claim_timestamp: 1_650_000_000,
+        };
+        // Legacy accounts were allocated with spare bytes after the fields
+        let mut data = User::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).unwrap();
+        data.resize(52, 0);
+
+        let decoded: LegacyUser = decode_legacy_account(&data, User::DISCRIMINATOR).unwrap();
+        let mut user = user_from_legacy(decoded, 1_700_000_500);
+        assert_eq!(user.amount, 5_000);
+        assert_eq!(user.tier_index, 1);
+        assert_eq!(user.lock_end_timestamp, 1_700_000_000);
+        assert_eq!(user.continuous_stake_start, 1_700_000_500);
+        assert!(!user.accumulator_migrated);
+
+        let pool = Pool {
+            reward_per_token_stored: 42 * REWARD_PRECISION,
+            ..Default::default()
+        };
+        migrate_legacy_user(&pool, &mut user, ms(1_700_000_500)).unwrap();
+        assert_eq!(user.reward_per_token_paid, pool.reward_per_token_stored);
+
+        let mut migrated = Vec::new();
+        user.try_serialize(&mut migrated).unwrap();
+        assert!(migrated.len() <= 8 + User::LEN);
+
+        // A pool account is not a user, whatever its length
+        assert!(decode_legacy_account::<LegacyUser>(&data, Pool::DISCRIMINATOR).is_err());
+    }
+
+    #[test]
+    fn legacy_pools_restart_emissions_at_migration() {
+        let authority = Pubkey::new_unique();
+        let legacy = LegacyPool {
+            bump: 255,
+            pool_id: [7; 32],
+            reward_per_second: 100,
+            lock_duration: 2_592_000,
+            lock_bonus_percentage: 5000,
+            total_staked: 9_000,
+            last_update_timestamp: 1_600_000_000,
+            authority,
+            ..Default::default()
+        };
+        let mut data = Pool::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).unwrap();
+
+        let decoded: LegacyPool = decode_legacy_account(&data, Pool::DISCRIMINATOR).unwrap();
+        let clock = Clock {
+            slot: 500,
+            unix_timestamp: 1_700_000_000,
+            ..Default::default()
+        };
+        let mut pool = pool_from_legacy(decoded, &clock).unwrap();
+        assert_eq!(pool.authority, authority);
+        assert_eq!(pool.total_staked, 9_000);
+        assert_eq!(pool.last_update_millis, ms(1_700_000_000));
+        assert_eq!(pool.lock_tiers, Vec::new());
+        assert_eq!(lock_tiers(&pool), default_lock_tiers(2_592_000, 5000));
+
+        // Nothing accrues for the time spent on the old layout
+        assert_eq!(reward_per_token(&pool, ms(1_700_000_000)).unwrap(), 0);
+        update_pool_rewards(&mut pool, ms(1_700_000_010)).unwrap();
+        assert_eq!(pool.total_reward_distributed, 1_000);
+    }
+
+    #[test]
+    fn closing_requires_claimable_rewards_to_be_claimed() {
+        let mut pool = Pool::default();
+        let mut user = User {
+            rewards_earned: 500,
+            cumulative_rewards: 1_000,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ensure_rewards_claimed(&pool, &user).unwrap_err(),
+            error!(ErrorCode::UnclaimedRewards)
+        );
+
+        // Rewards past the per-user cap can never be paid, so they don't block a close
+        pool.max_reward_per_user = 1_000;
+        assert!(ensure_rewards_claimed(&pool, &user).is_ok());
+
+        pool.max_reward_per_user = 0;
+        user.rewards_earned = 0;
+        assert!(ensure_rewards_claimed(&pool, &user).is_ok());
+    }
+
+    #[test]
+    fn lock_tiers_mirror_pool_configuration() {
+        let mut pool = Pool {
+            lock_duration: 2_592_000,
+            lock_bonus_percentage: 5000,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            lock_tiers(&pool),
+            vec![
+                LockTier { duration: 0, bonus_bps: 0 },
+                LockTier { duration: 2_592_000, bonus_bps: 5000 },
+            ]
+        );
+
+        // Stored tiers take precedence over the legacy lock fields
+        let stored = vec![
+            LockTier { duration: 0, bonus_bps: 0 },
+            LockTier { duration: 604_800, bonus_bps: 1000 },
+            LockTier { duration: 7_776_000, bonus_bps: 10000 },
+        ];
+        pool.lock_tiers = stored.clone();
+        assert_eq!(lock_tiers(&pool), stored);
+        assert!(has_custom_lock_tiers(&pool));
+
+        // The default pair for the legacy fields isn't custom, stored or not
+        pool.lock_tiers = default_lock_tiers(2_592_000, 5000);
+        assert!(!has_custom_lock_tiers(&pool));
+        pool.lock_tiers.clear();
+        assert!(!has_custom_lock_tiers(&pool));
+    }
+
+    #[test]
+    fn late_claim_is_paid_exactly_up_to_reward_end() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1,
+            total_staked: 100,
+            last_update_millis: ms(start),
+            reward_start_timestamp: start,
+            reward_end_timestamp: start + 100,
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(start),
+            ..Default::default()
+        };
+
+        let owed = compute_pending_rewards(&pool, &user, ms(start + 100)).unwrap();
+        assert_eq!(owed, 100);
+
+        // Waiting well past the end earns nothing more
+        assert_eq!(compute_pending_rewards(&pool, &user, ms(start + 500)).unwrap(), owed);
+
+        // Pool emission accounting stops at the end too
+        update_pool_rewards(&mut pool, ms(start + 500)).unwrap();
+        assert_eq!(pool.total_reward_distributed, 100);
+
+        // A claim that started after the end accrues nothing
+        let after_end = User {
+            last_reward_claim_millis: ms(start + 200),
+            reward_per_token_paid: pool.reward_per_token_stored,
+            ..user.clone()
+        };
+        assert_eq!(compute_pending_rewards(&pool, &after_end, ms(start + 500)).unwrap(), 0);
+    }
+
+    #[test]
+    fn protocol_fee_splits_claimed_rewards() {
+        let mut pool = Pool {
+            reward_protocol_fee_bps: 500,
+            ..Default::default()
+        };
+
+        assert_eq!(split_protocol_fee(&pool, 10_000).unwrap(), (9_500, 500));
+        // The fee rounds down in the user's favour
+        assert_eq!(split_protocol_fee(&pool, 19).unwrap(), (19, 0));
+
+        pool.reward_protocol_fee_bps = 0;
+        assert_eq!(split_protocol_fee(&pool, 10_000).unwrap(), (10_000, 0));
+    }
+
+    #[test]
+    fn unstake_fee_grows_reserve_for_remaining_stakers() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            total_staked: 200,
+            unstake_fee_bps: 500,
+            reward_reserve: 1_000,
+            reward_per_token_stored: 7 * REWARD_PRECISION,
+            last_update_millis: ms(now),
+            ..Default::default()
+        };
+        let stayer = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now),
+            reward_per_token_paid: 7 * REWARD_PRECISION,
+            ..Default::default()
+        };
+
+        assert_eq!(compute_pending_rewards(&pool, &stayer, ms(now)).unwrap(), 0);
+
+        // Another staker exits 100 and leaves 5% behind for whoever remains
+        let fee = unstake_fee(&pool, 100).unwrap();
+        assert_eq!(fee, 5);
+        pool.total_staked -= 100;
+        pool.reward_reserve += fee;
+        distribute_to_stakers(&mut pool, fee).unwrap();
+
+        assert_eq!(compute_pending_rewards(&pool, &stayer, ms(now)).unwrap(), 5);
+        assert_eq!(compute_claimable_rewards(&pool, &stayer, ms(now)).unwrap(), 5);
+
+        pool.unstake_fee_bps = 0;
+        assert_eq!(unstake_fee(&pool, 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn closing_a_position_burns_all_its_lst() {
+        let mut user = User {
+            amount: 60_000_000,
+            lst_minted: 60_000_000,
+            ..Default::default()
+        };
+        assert_eq!(lst_to_burn(&user).unwrap(), 0);
+
+        // Close zeroes the stake first, leaving every issued LST uncovered
+        user.amount = 0;
+        assert_eq!(lst_to_burn(&user).unwrap(), 60_000_000);
+    }
+
+    #[test]
+    fn lst_previews_round_trip() {
+        for amount in [0, 1, 40_000_000, u64::MAX] {
+            let lst = underlying_to_lst(amount).unwrap();
+            assert_eq!(lst_to_underlying(lst).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn emission_status_tracks_reward_window() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_start_timestamp: start,
+            reward_end_timestamp: start + 100,
+            ..Default::default()
+        };
+
+        let before = emission_status(&pool, start - 1).unwrap();
+        assert_eq!(before, EmissionStatus { emitting: false, seconds_remaining: 0 });
+
+        let active = emission_status(&pool, start + 40).unwrap();
+        assert_eq!(active, EmissionStatus { emitting: true, seconds_remaining: 60 });
+
+        let finished = emission_status(&pool, start + 100).unwrap();
+        assert_eq!(finished, EmissionStatus { emitting: false, seconds_remaining: 0 });
+
+        pool.reward_end_timestamp = 0;
+        assert_eq!(emission_status(&pool, start + 1_000).unwrap().seconds_remaining, u64::MAX);
+        assert!(!emission_status(&pool, start - 1).unwrap().emitting);
+    }
+
+    #[test]
+    fn user_position_matches_individual_views() {
+        let now = 1_700_000_000;
+        let pool = Pool {
+            reward_per_second: 1_000_000,
+            total_staked: 100_000_000,
+            reward_reserve: 1_000_000_000,
+            lock_duration: 86_400,
+            last_update_millis: ms(now - 600),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100_000_000,
+            lock_type: 1,
+            lock_end_timestamp: now + 86_400,
+            bonus_multiplier: 15000,
+            last_reward_claim_millis: ms(now - 600),
+            ..Default::default()
+        };
+
+        let position = user_position(&pool, &user, ms(now)).unwrap();
+        assert_eq!(position.amount, user.amount);
+        assert_eq!(position.lock_type, 1);
+        assert_eq!(position.lock_end_timestamp, now + 86_400);
+        assert_eq!(position.multiplier_bps, effective_multiplier(&pool, &user, now).unwrap());
+        assert_eq!(position.pending_rewards, compute_pending_rewards(&pool, &user, ms(now)).unwrap());
+        assert_eq!(
+            position.claimable_rewards,
+            compute_claimable_rewards(&pool, &user, ms(now)).unwrap()
+        );
+        assert_eq!(position.claimable_rewards, 900_000_000);
+    }
+
+    #[test]
+    fn unknown_lock_type_is_rejected() {
+        let pool = Pool {
+            lock_duration: 2_592_000,
+            lock_bonus_percentage: 5000,
+            ..Default::default()
+        };
+
+        assert_eq!(resolve_lock_tier(&pool, 0).unwrap().duration, 0);
+        assert_eq!(resolve_lock_tier(&pool, 1).unwrap().duration, 2_592_000);
+        assert_eq!(
+            resolve_lock_tier(&pool, 2).unwrap_err(),
+            error!(ErrorCode::InvalidLockType)
+        );
+    }
+
+    #[test]
+    fn lock_tiers_must_be_bounded_and_ascending() {
+        let tier = |duration: u64, bonus_bps: u16| LockTier { duration, bonus_bps };
+
+        let ascending = [tier(0, 0), tier(604_800, 1000), tier(2_592_000, 5000)];
+        assert!(validate_lock_tiers(&ascending).is_ok());
+
+        let out_of_order = [tier(0, 0), tier(2_592_000, 5000), tier(604_800, 1000)];
+        assert_eq!(
+            validate_lock_tiers(&out_of_order).unwrap_err(),
+            error!(ErrorCode::InvalidConfiguration)
+        );
+
+        let duplicate = [tier(0, 0), tier(0, 1000)];
+        assert!(validate_lock_tiers(&duplicate).is_err());
+
+        let shrinking_bonus = [tier(0, 0), tier(604_800, 5000), tier(2_592_000, 1000)];
+        assert!(validate_lock_tiers(&shrinking_bonus).is_err());
+
+        // Tier 0 is always flexible
+        let locked_only = [tier(604_800, 1000), tier(2_592_000, 5000)];
+        assert!(validate_lock_tiers(&locked_only).is_err());
+
+        let too_many: Vec<LockTier> = (0..=MAX_LOCK_TIERS as u64).map(|i| tier(i, 0)).collect();
+        assert!(validate_lock_tiers(&too_many).is_err());
+        assert!(validate_lock_tiers(&[]).is_err());
+
+        // 10000 + bonus must fit the u16 multiplier
+        assert!(validate_lock_tiers(&[tier(0, 0), tier(604_800, u16::MAX - 10000)]).is_ok());
+        assert!(validate_lock_tiers(&[tier(0, 0), tier(604_800, u16::MAX - 9999)]).is_err());
+    }
+
+    #[test]
+    fn amount_curve_scales_rewards_by_bracket() {
+        let now = 1_700_000_000;
+        let point = |threshold: u64, multiplier_bps: u16| AmountMultiplier { threshold, multiplier_bps };
+        let mut pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 1_000_000,
+            last_update_millis: ms(now - 100),
+            ..Default::default()
+        };
+        let user = |amount: u64| User {
+            amount,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
+        let small = user(100_000);
+        let large = user(900_000);
+
+        // Without a curve, rewards are proportional to stake
+        assert_eq!(compute_pending_rewards(&pool, &small, ms(now)).unwrap(), 10_000);
+        assert_eq!(compute_pending_rewards(&pool, &large, ms(now)).unwrap(), 90_000);
+
+        pool.amount_multiplier_curve = vec![point(0, 9000), point(500_000, 12000)];
+        assert!(validate_amount_multiplier_curve(&pool.amount_multiplier_curve).is_ok());
+        assert_eq!(compute_pending_rewards(&pool, &small, ms(now)).unwrap(), 9_000);
+        assert_eq!(compute_pending_rewards(&pool, &large, ms(now)).unwrap(), 108_000);
+
+        // Below the first threshold earns 1x
+        pool.amount_multiplier_curve = vec![point(500_000, 12000)];
+        assert_eq!(compute_pending_rewards(&pool, &small, ms(now)).unwrap(), 10_000);
+
+        assert!(validate_amount_multiplier_curve(&[point(500_000, 12000), point(0, 9000)]).is_err());
+        assert!(validate_amount_multiplier_curve(&[point(0, 9000), point(0, 12000)]).is_err());
+        assert!(validate_amount_multiplier_curve(&[point(0, 0)]).is_err());
+    }
+
+    #[test]
+    fn amount_curve_change_reprices_the_unsettled_interval() {
+        let start = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 10,
+            total_staked: 100,
+            last_update_millis: ms(start),
+            ..Default::default()
+        };
+        let user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(start),
+            ..Default::default()
+        };
+
+        // 100s accrued at 1x, then the curve changes before the user settles
+        let later = ms(start + 100);
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 1_000);
+        pool.amount_multiplier_curve = vec![AmountMultiplier { threshold: 0, multiplier_bps: 15000 }];
+        assert_eq!(compute_pending_rewards(&pool, &user, later).unwrap(), 1_500);
+    }
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    #[test]
+    fn pair_bonus_applies_only_to_the_current_pairing() {
+        let now = 1_700_000_000;
+        let paired = Pubkey::new_unique();
+        let mut pool = Pool {
+            reward_per_second: 1_000,
+            total_staked: 1_000_000,
+            last_update_millis: ms(now - 100),
+            paired_pool: Some(paired),
+            pair_bonus_bps: 5000,
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100_000,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
 
-    pub system_program: Program<'info, System>,
-}
+        // Not verified yet
+        assert_eq!(pair_bonus_bps(&pool, &user), 0);
+        assert_eq!(compute_pending_rewards(&pool, &user, ms(now)).unwrap(), 10_000);
 
-#[derive(Accounts)]
-pub struct CreateUserAccount<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+        user.verified_paired_pool = paired;
+        user.pair_bonus_bps = pool.pair_bonus_bps;
+        assert_eq!(pair_bonus_bps(&pool, &user), 5000);
+        assert_eq!(compute_pending_rewards(&pool, &user, ms(now)).unwrap(), 15_000);
 
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + User::LEN,
-        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
-        bump
-    )]
-    pub user: Account<'info, User>,
+        // A new bonus only reaches the user when they verify again
+        pool.pair_bonus_bps = 10000;
+        assert_eq!(pair_bonus_bps(&pool, &user), 5000);
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        // Re-pairing the pool drops bonuses verified against the old pair
+        pool.paired_pool = Some(Pubkey::new_unique());
+        assert_eq!(pair_bonus_bps(&pool, &user), 0);
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    #[test]
+    fn settlement_revokes_a_pair_bonus_it_cannot_recheck() {
+        let paired = Pubkey::new_unique();
+        let pool = Pool {
+            paired_pool: Some(paired),
+            pair_bonus_bps: 5000,
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100_000,
+            verified_paired_pool: paired,
+            verified_paired_user: Pubkey::new_unique(),
+            pair_bonus_bps: 5000,
+            ..Default::default()
+        };
 
-#[derive(Accounts)]
-#[instruction(pool_id: [u8; 32])]
-pub struct CreatePool<'info> {
-    #[account(
-        mut,
-        seeds = [b"global"],
-        bump = global_state.bump
-    )]
-    pub global_state: Account<'info, GlobalState>,
+        // Without the paired position to read, the bonus can't be shown to still apply
+        recheck_pair_bonus(&pool, &mut user, &[], &crate::ID).unwrap();
+        assert_eq!(user.pair_bonus_bps, 0);
+        assert_eq!(user.verified_paired_pool, Pubkey::default());
+        assert_eq!(pair_bonus_bps(&pool, &user), 0);
+    }
 
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + Pool::LEN,
-        seeds = [b"pool", pool_id.as_ref()],
-        bump
-    )]
-    pub pool: Account<'info, Pool>,
+    #[test]
+    fn stake_limits_hold_at_the_boundaries() {
+        let mut pool = Pool {
+            min_stake: 100,
+            ..Default::default()
+        };
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        // No cap: only the minimum applies
+        assert!(check_stake_limits(&pool, 0, 99).is_err());
+        assert!(check_stake_limits(&pool, 0, 100).is_ok());
+        assert!(check_stake_limits(&pool, u64::MAX - 100, 100).is_ok());
 
-    pub authority: Signer<'info>,
+        pool.max_stake_per_user = 1_000;
+        assert!(check_stake_limits(&pool, 900, 100).is_ok());
+        assert!(check_stake_limits(&pool, 901, 100).is_err());
+        assert!(check_stake_limits(&pool, 0, 1_001).is_err());
 
-    pub system_program: Program<'info, System>,
-}
+        // Capacity counts every position, not just this one
+        pool.max_total_staked = 5_000;
+        pool.total_staked = 4_900;
+        assert!(check_stake_limits(&pool, 0, 100).is_ok());
+        assert!(check_stake_limits(&pool, 0, 101).is_err());
 
-#[derive(Accounts)]
-#[instruction(amount: u64, lock_type: u8)]
-pub struct Stake<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+        assert!(validate_stake_limits(100, 0).is_ok());
+        assert!(validate_stake_limits(100, 100).is_ok());
+        assert!(validate_stake_limits(100, 99).is_err());
+    }
 
-    #[account(
-        mut,
-        seeds = [b"user", pool.pool_id.as_ref(), payer.key().as_ref()],
-        bump
-    )]
-    pub user: Account<'info, User>,
+    #[test]
+    fn sub_1x_bonus_multiplier_is_rejected() {
+        let mut user = User::default();
+        assert_eq!(user.bonus_multiplier, 10000);
 
-    /// CHECK: Mint account for the stake token
-    pub stake_mint: Account<'info, Mint>,
+        assert!(set_bonus_multiplier(&mut user, 0).is_err());
+        assert!(set_bonus_multiplier(&mut user, 9999).is_err());
+        assert_eq!(user.bonus_multiplier, 10000);
 
-    /// CHECK: Pool authority account (receives staked tokens/lamports)
-    #[account(mut)]
-    pub pool_authority: AccountInfo<'info>,
+        set_bonus_multiplier(&mut user, 15000).unwrap();
+        assert_eq!(user.bonus_multiplier, 15000);
+    }
 
-    /// CHECK: Pool authority's token account (receives staked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub pool_authority_token_account: Option<AccountInfo<'info>>,
+    #[test]
+    fn locked_top_up_extends_end_and_blends_multiplier() {
+        let now: i64 = 1_700_000_000;
+        let day: i64 = 86_400;
+        let tier = LockTier { duration: 30 * day as u64, bonus_bps: 5000 };
+        let user = User {
+            amount: 300,
+            lock_type: 1,
+            lock_end_timestamp: now + 10 * day,
+            bonus_multiplier: 15000,
+            ..Default::default()
+        };
 
-    /// CHECK: User's token account
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub user_token_account: Option<AccountInfo<'info>>,
+        // Mid-lock top-up: the new tokens get their full lock
+        let (end, multiplier) = topped_up_lock(&user, &tier, 100, now).unwrap();
+        assert_eq!(end, now + 30 * day);
+        assert_eq!(multiplier, 15000);
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
+        // Bonus was cut since the old tokens locked: blend by stake
+        let cheaper = LockTier { duration: 30 * day as u64, bonus_bps: 1000 };
+        let (_, multiplier) = topped_up_lock(&user, &cheaper, 100, now).unwrap();
+        assert_eq!(multiplier, 14000); // (300 * 1.5x + 100 * 1.1x) / 400
 
-    /// CHECK: Token program or Token-2022 program
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub token_program: Option<AccountInfo<'info>>,
+        // A shorter tier never pulls the unlock time earlier
+        let short = LockTier { duration: day as u64, bonus_bps: 5000 };
+        let (end, _) = topped_up_lock(&user, &short, 100, now).unwrap();
+        assert_eq!(end, now + 10 * day);
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    #[test]
+    fn auto_compound_moves_banked_rewards_into_stake() {
+        let mint = Pubkey::new_unique();
+        let mut pool = Pool {
+            stake_mint: mint,
+            reward_mint: mint,
+            total_staked: 1_000,
+            reward_reserve: 500,
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100,
+            rewards_earned: 40,
+            ..Default::default()
+        };
 
-#[derive(Accounts)]
-pub struct Unstake<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+        // Accumulate mode leaves rewards banked
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, u64::MAX).unwrap(), 0);
 
-    #[account(
-        mut,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
-    )]
-    pub user: Account<'info, User>,
+        // Limited by what the vault holds; the rest stays banked
+        user.reward_mode = 1;
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, 30).unwrap(), 30);
+        assert_eq!((user.amount, user.rewards_earned), (130, 10));
+        assert_eq!((pool.total_staked, pool.reward_reserve), (1_030, 470));
 
-    /// CHECK: Mint account for the stake token
-    pub stake_mint: Account<'info, Mint>,
+        // Fee-charging pools leave the remainder for an explicit compound
+        pool.reward_protocol_fee_bps = 500;
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, u64::MAX).unwrap(), 0);
+    }
 
-    /// CHECK: Pool authority account (holds staked tokens/lamports)
-    #[account(mut)]
-    pub pool_authority: AccountInfo<'info>,
+    #[test]
+    fn compounding_respects_stake_limits() {
+        let mint = Pubkey::new_unique();
+        let mut pool = Pool {
+            stake_mint: mint,
+            reward_mint: mint,
+            total_staked: 1_000,
+            reward_reserve: 500,
+            min_stake: 100,
+            max_stake_per_user: 120,
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100,
+            rewards_earned: 40,
+            reward_mode: 1,
+            ..Default::default()
+        };
 
-    /// CHECK: Pool authority's token account (holds staked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub pool_authority_token_account: Option<AccountInfo<'info>>,
+        // Explicit compounds are held to the caps but not the deposit minimum
+        assert!(check_stake_capacity(&pool, user.amount, 20).is_ok());
+        assert_eq!(
+            check_stake_capacity(&pool, user.amount, 21).unwrap_err(),
+            error!(ErrorCode::ExceedsMaxStake)
+        );
 
-    /// CHECK: User's token account (receives unstaked SPL tokens)
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub user_token_account: Option<AccountInfo<'info>>,
+        // Auto-compound only fills the position up to its cap
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, u64::MAX).unwrap(), 20);
+        assert_eq!((user.amount, user.rewards_earned), (120, 20));
 
-    pub authority: Signer<'info>,
+        // ...and never past the pool's capacity
+        pool.max_stake_per_user = 0;
+        pool.max_total_staked = 1_025;
+        assert_eq!(stake_headroom(&pool, user.amount), 5);
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, u64::MAX).unwrap(), 5);
+        assert_eq!(pool.total_staked, 1_025);
+        assert_eq!(apply_auto_compound(&mut pool, &mut user, u64::MAX).unwrap(), 0);
+        assert_eq!(user.rewards_earned, 15);
+    }
 
-    /// CHECK: Token program or Token-2022 program
-    /// Optional: Only required for SPL tokens, not native SOL
-    pub token_program: Option<AccountInfo<'info>>,
+    #[test]
+    fn staker_count_tracks_only_empty_to_staked_transitions() {
+        let mut pool = Pool::default();
 
-    pub system_program: Program<'info, System>,
-}
+        track_staker_transition(&mut pool, 0, 100).unwrap();
+        track_staker_transition(&mut pool, 0, 50).unwrap();
+        assert_eq!(pool.staker_count, 2);
 
-#[derive(Accounts)]
-pub struct ClaimRewards<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+        // Top-ups and partial exits are not transitions
+        track_staker_transition(&mut pool, 100, 300).unwrap();
+        track_staker_transition(&mut pool, 300, 10).unwrap();
+        assert_eq!(pool.staker_count, 2);
 
-    #[account(
-        mut,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
-    )]
-    pub user: Account<'info, User>,
+        track_staker_transition(&mut pool, 10, 0).unwrap();
+        assert_eq!(pool.staker_count, 1);
 
-    pub authority: Signer<'info>,
-}
+        // Never underflows for positions that predate the counter
+        track_staker_transition(&mut pool, 50, 0).unwrap();
+        track_staker_transition(&mut pool, 70, 0).unwrap();
+        assert_eq!(pool.staker_count, 0);
+    }
 
-#[derive(Accounts)]
-pub struct UpdatePool<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+    #[test]
+    fn unstake_burns_only_lst_beyond_remaining_stake() {
+        let mut user = User {
+            amount: 1_000,
+            lst_minted: 600,
+            ..Default::default()
+        };
 
-    pub authority: Signer<'info>,
-}
+        // Remaining stake still covers the issued LST
+        assert_eq!(lst_to_burn(&user).unwrap(), 0);
 
-#[derive(Accounts)]
-pub struct CloseUserAccount<'info> {
-    #[account(
-        mut,
-        seeds = [b"pool", pool.pool_id.as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Account<'info, Pool>,
+        user.amount = 250;
+        assert_eq!(lst_to_burn(&user).unwrap(), 350);
 
-    #[account(
-        mut,
-        close = user_wallet,
-        seeds = [b"user", pool.pool_id.as_ref(), authority.key().as_ref()],
-        bump = user.bump
-    )]
-    pub user: Account<'info, User>,
+        user.amount = 0;
+        assert_eq!(lst_to_burn(&user).unwrap(), 600);
+    }
 
-    /// CHECK: User wallet
-    #[account(mut)]
-    pub user_wallet: AccountInfo<'info>,
+    #[test]
+    fn partial_claim_leaves_remainder_accrued() {
+        let now = 1_700_000_000;
+        let mut pool = Pool {
+            reward_per_second: 1,
+            total_staked: 100,
+            last_update_millis: ms(now - 10),
+            ..Default::default()
+        };
+        let mut user = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 10),
+            ..Default::default()
+        };
 
-    pub authority: Signer<'info>,
-}
+        let pending = compute_pending_rewards(&pool, &user, ms(now)).unwrap();
+        let (claimed, remainder) = split_partial_claim(pending, 5000).unwrap();
+        assert_eq!(claimed, pending / 2);
+        assert_eq!(claimed + remainder, pending);
 
-// ============ Data Structures ============
+        // The remainder is still owed, on top of new accrual
+        update_pool_rewards(&mut pool, ms(now)).unwrap();
+        user.rewards_earned = remainder;
+        user.last_reward_claim_millis = ms(now);
+        user.reward_per_token_paid = pool.reward_per_token_stored;
+        assert_eq!(compute_pending_rewards(&pool, &user, ms(now)).unwrap(), remainder);
+        assert!(compute_pending_rewards(&pool, &user, ms(now + 1)).unwrap() > remainder);
 
-#[account]
-pub struct GlobalState {
-    pub bump: u8,
-    pub authority: Pubkey,
-    pub pool_count: u64,
-}
+        assert_eq!(split_partial_claim(pending, 10000).unwrap(), (pending, 0));
+    }
 
-impl GlobalState {
-    pub const LEN: usize = 8 + 32 + 8; // bump + authority + pool_count
-}
+    #[test]
+    fn same_slot_unstakes_are_order_independent() {
+        let now = 1_700_000_000;
+        let clock = Clock {
+            slot: 500,
+            unix_timestamp: now,
+            ..Default::default()
+        };
+        let staker = User {
+            amount: 100,
+            bonus_multiplier: 10000,
+            last_reward_claim_millis: ms(now - 100),
+            ..Default::default()
+        };
+        let fresh_pool = || Pool {
+            reward_per_second: 4,
+            total_staked: 400,
+            checkpoint_slot: 499,
+            last_update_millis: ms(now - 100),
+            ..Default::default()
+        };
 
-#[account]
-pub struct Pool {
-    pub bump: u8,
-    pub pool_id: [u8; 32],          // Pool identifier (e.g., "wave", "wealth")
-    pub stake_mint: Pubkey,         // Token being staked
-    pub lst_mint: Pubkey,           // Liquid Staking Token mint
-    pub reward_mint: Pubkey,        // Reward token mint
-    pub reward_per_second: u64,     // Base reward rate
-    pub lock_duration: u64,         // Lock duration in seconds (2592000 = 30 days)
-    pub lock_bonus_percentage: u16, // Bonus percentage (5000 = 50%)
-    pub total_staked: u64,          // Total tokens staked in pool
-    pub total_reward_distributed: u64, // Total rewards distributed
-    pub last_update_timestamp: i64, // Last time pool was updated
-    pub authority: Pubkey,          // Pool authority
-}
+        // Each exit settles its rewards, then leaves the pool in the same slot
+        let exit_in_order = |order: [usize; 3]| {
+            let mut pool = fresh_pool();
+            let mut paid = [0u64; 3];
+            for who in order {
+                let now_ms = touch_clock(&mut pool, &clock).unwrap();
+                update_pool_rewards(&mut pool, now_ms).unwrap();
+                paid[who] = compute_pending_rewards(&pool, &staker, now_ms).unwrap();
+                pool.total_staked -= staker.amount;
+            }
+            paid
+        };
 
-impl Pool {
-    pub const LEN: usize = 8 + // discriminator
-        32 + // pool_id
-        32 + // stake_mint
-        32 + // lst_mint
-        32 + // reward_mint
-        8 +  // reward_per_second
-        8 +  // lock_duration
-        2 +  // lock_bonus_percentage
-        8 +  // total_staked
-        8 +  // total_reward_distributed
-        8 +  // last_update_timestamp
-        32;  // authority
-}
+        let forward = exit_in_order([0, 1, 2]);
+        let backward = exit_in_order([2, 1, 0]);
+        assert_eq!(forward, backward);
+        assert_eq!(forward, [100, 100, 100]);
+    }
 
-#[account]
-pub struct User {
-    pub bump: u8,
-    pub amount: u64,                  // Amount staked
-    pub lock_type: u8,                // 0 = flexible, 1 = locked
-    pub lock_start_timestamp: i64,    // Lock start time
-    pub lock_end_timestamp: i64,      // Lock end time
-    pub bonus_multiplier: u16,        // Reward multiplier (10000 = 1x)
-    pub last_reward_claim_timestamp: i64, // Last reward claim
-}
+    #[test]
+    fn early_unstake_penalty_goes_to_fee_recipient() {
+        let authority = Pubkey::new_unique();
+        let mut pool = Pool {
+            authority,
+            early_unstake_penalty_bps: 1000,
+            ..Default::default()
+        };
 
-impl User {
-    pub const LEN: usize = 8 + // discriminator
-        1 + // bump
-        8 + // amount
-        1 + // lock_type
-        8 + // lock_start_timestamp
-        8 + // lock_end_timestamp
-        2 + // bonus_multiplier
-        8;  // last_reward_claim_timestamp
-}
+        assert_eq!(early_unstake_penalty(&pool, 50_000).unwrap(), 5_000);
+        assert_eq!(penalty_recipient(&pool), authority);
 
-// ============ Error Codes ============
+        let treasury = Pubkey::new_unique();
+        pool.protocol_fee_recipient = treasury;
+        assert_eq!(penalty_recipient(&pool), treasury);
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Invalid amount provided")]
-    InvalidAmount,
-    #[msg("Insufficient staked amount")]
-    InsufficientStake,
-    #[msg("Still in lock period")]
-    StillInLockPeriod,
-    #[msg("Math overflow occurred")]
-    MathOverflow,
-    #[msg("No rewards available to claim")]
-    NoRewardsAvailable,
-    #[msg("Invalid token mint")]
-    InvalidMint,
-    #[msg("Invalid token program")]
-    InvalidTokenProgram,
+        pool.early_unstake_penalty_bps = 0;
+        assert_eq!(early_unstake_penalty(&pool, 50_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn pool_lock_rejects_reentry() {
+        let mut pool = Pool::default();
+
+        acquire_pool_lock(&mut pool).unwrap();
+        assert_eq!(
+            acquire_pool_lock(&mut pool).unwrap_err(),
+            error!(ErrorCode::Reentrancy)
+        );
+
+        pool.locked = false;
+        assert!(acquire_pool_lock(&mut pool).is_ok());
+    }
 }
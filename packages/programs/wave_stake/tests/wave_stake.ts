@@ -2,6 +2,16 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { WaveStake } from "../target/types/wave_stake";
 import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import {
+  NATIVE_MINT,
+  TOKEN_PROGRAM_ID,
+  createAccount,
+  createMint,
+  getAccount,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+  transfer,
+} from "@solana/spl-token";
 import { assert } from "chai";
 
 describe("wave_stake", () => {
@@ -58,6 +68,186 @@ describe("wave_stake", () => {
     await provider.connection.confirmTransaction(signature);
   });
 
+  // Pool and user PDAs for a pool named `name`; the user defaults to the provider wallet
+  const poolAddresses = (name: string, owner = provider.wallet.publicKey) => {
+    const id = Buffer.alloc(32);
+    Buffer.from(name).copy(id);
+    const [pool] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool"), id],
+      program.programId
+    );
+    const [user] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user"), id, owner.toBuffer()],
+      program.programId
+    );
+    return { id, pool, user };
+  };
+
+  // Sends create_pool with the suite's default emission and lock settings
+  const openPool = (
+    id: Buffer,
+    pool: PublicKey,
+    stakeMint: PublicKey,
+    lstMint = LST_MINT,
+    rewardMint = REWARD_MINT,
+    { lockDuration = LOCK_DURATION, earlyUnstakePenaltyBps = 0 } = {}
+  ) =>
+    program.methods
+      .createPool(
+        Array.from(id),
+        stakeMint,
+        lstMint,
+        rewardMint,
+        REWARD_PER_SECOND,
+        lockDuration,
+        LOCK_BONUS_PERCENTAGE,
+        null,
+        earlyUnstakePenaltyBps,
+        null,
+        new anchor.BN(0),
+        new anchor.BN(0),
+        new anchor.BN(0)
+      )
+      .accounts({
+        globalState: globalStatePDA,
+        pool,
+        payer: provider.wallet.publicKey,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+  // Opens a user account on `pool`, paid and signed by `owner` when given,
+  // otherwise by the provider wallet
+  const openUserAccount = (pool: PublicKey, user: PublicKey, owner?: Keypair) => {
+    const call = program.methods
+      .createUserAccount()
+      .accounts({
+        pool,
+        user,
+        payer: owner ? owner.publicKey : provider.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      });
+    return (owner ? call.signers([owner]) : call).rpc();
+  };
+
+  // A fresh wallet holding enough SOL for fees and rent
+  const fundedKeypair = async () => {
+    const keypair = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      keypair.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+    return keypair;
+  };
+
+  // Creates a pool backed by freshly minted stake and reward tokens, with a
+  // user account and stake-token balance for the provider wallet.
+  // Staked principal is held in the pool PDA's associated stake-token account.
+  // With singleMint, the pool pays rewards in its own stake token from a
+  // separate pool-owned vault.
+  // With poolLst, the pool's LST mint is created with the pool PDA as mint authority.
+  const createTokenPool = async (
+    name: string,
+    singleMint = false,
+    earlyUnstakePenaltyBps = 0,
+    poolLst = false
+  ) => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const { id, pool, user } = poolAddresses(name);
+
+    const stakeMint = await createMint(provider.connection, payer, payer.publicKey, null, 6);
+    const rewardMint = singleMint
+      ? stakeMint
+      : await createMint(provider.connection, payer, payer.publicKey, null, 6);
+    const lstMint = poolLst
+      ? await createMint(provider.connection, payer, pool, null, 6)
+      : LST_MINT;
+    const userStake = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, stakeMint, provider.wallet.publicKey
+    );
+    const userReward = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, rewardMint, provider.wallet.publicKey
+    );
+    const poolAuthorityStake = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, stakeMint, authority.publicKey
+    );
+    const stakeVault = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, stakeMint, pool, true
+    );
+    const rewardVault = singleMint
+      ? await createAccount(provider.connection, payer, rewardMint, pool, Keypair.generate())
+      : (await getOrCreateAssociatedTokenAccount(
+          provider.connection, payer, rewardMint, pool, true
+        )).address;
+    const authorityReward = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, rewardMint, authority.publicKey
+    );
+    await mintTo(provider.connection, payer, stakeMint, userStake.address, payer, 1_000 * 1e6);
+    await mintTo(provider.connection, payer, rewardMint, userReward.address, payer, 1_000 * 1e6);
+    await mintTo(provider.connection, payer, rewardMint, authorityReward.address, payer, 1_000 * 1e6);
+
+    await openPool(id, pool, stakeMint, lstMint, rewardMint, { earlyUnstakePenaltyBps });
+    await openUserAccount(pool, user);
+
+    const stakeAccounts = {
+      pool,
+      user,
+      stakeMint,
+      stakeVault: stakeVault.address,
+      userTokenAccount: userStake.address,
+      payer: provider.wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    };
+
+    const depositAccounts = {
+      pool,
+      rewardMint,
+      rewardVault,
+      depositorTokenAccount: userReward.address,
+      depositor: provider.wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    // Deposits signed by the pool authority, which alone can end open-ended emissions
+    const authorityDepositAccounts = {
+      ...depositAccounts,
+      depositorTokenAccount: authorityReward.address,
+      depositor: authority.publicKey,
+    };
+
+    const claimAccounts = {
+      pool,
+      user,
+      rewardMint,
+      rewardVault,
+      userRewardTokenAccount: userReward.address,
+      authority: provider.wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      protocolFeeAccount: null,
+    };
+
+    return {
+      pool,
+      user,
+      stakeMint,
+      rewardMint,
+      lstMint,
+      rewardVault,
+      userStake: userStake.address,
+      userReward: userReward.address,
+      poolAuthorityStake: poolAuthorityStake.address,
+      stakeVault: stakeVault.address,
+      stakeAccounts,
+      depositAccounts,
+      authorityDepositAccounts,
+      claimAccounts,
+    };
+  };
+
   it("Initializes the global state", async () => {
     const tx = await program.methods
       .initialize(authority.publicKey)
@@ -92,7 +282,13 @@ describe("wave_stake", () => {
         REWARD_MINT,
         REWARD_PER_SECOND,
         LOCK_DURATION,
-        LOCK_BONUS_PERCENTAGE
+        LOCK_BONUS_PERCENTAGE,
+        null, // Emissions start immediately
+        0, // No early-unstake penalty
+        null, // Default flexible + locked tiers
+        new anchor.BN(0), // No minimum stake
+        new anchor.BN(0), // No per-user cap
+        new anchor.BN(0) // Uncapped pool
       )
       .accounts({
         globalState: globalStatePDA,
@@ -127,7 +323,7 @@ describe("wave_stake", () => {
     const lockType = 0; // Flexible
 
     const tx = await program.methods
-      .stake(amount, lockType)
+      .stake(amount, lockType, null)
       .accounts({
         pool: poolPDA,
         user: userPDA,
@@ -178,23 +374,103 @@ describe("wave_stake", () => {
     console.log("   - Remaining:", user.amount.toString(), "tokens");
   });
 
+  it("Emits the stake memo in the Staked event", async () => {
+    const memo = Buffer.alloc(32);
+    Buffer.from("treasury-ref-0042").copy(memo);
+
+    let listener: number | undefined;
+    const event = new Promise<any>((resolve) => {
+      listener = program.addEventListener("staked", (e) => resolve(e));
+    });
+
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, Array.from(memo))
+      .accounts({
+        pool: poolPDA,
+        user: userPDA,
+        payer: provider.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const staked = await event;
+    await program.removeEventListener(listener!);
+
+    assert.deepEqual(Array.from(staked.memo), Array.from(memo));
+    assert.equal(staked.amount.toString(), new anchor.BN(1e6).toString());
+  });
+
+  it("Pokes the pool to settle emission accounting", async () => {
+    const before = await program.account.pool.fetch(poolPDA);
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    await program.methods.pokePool().accounts({ pool: poolPDA }).rpc();
+
+    const after = await program.account.pool.fetch(poolPDA);
+    const elapsed = after.lastUpdateTimestamp.sub(before.lastUpdateTimestamp);
+    assert.isTrue(elapsed.gtn(0));
+    if (before.totalStaked.gtn(0)) {
+      // Same settlement a stake would have applied
+      assert.equal(
+        after.totalRewardDistributed.sub(before.totalRewardDistributed).toString(),
+        before.rewardPerSecond.mul(elapsed).toString()
+      );
+    }
+  });
+
   it("Claims rewards", async () => {
+    const ctx = await createTokenPool("claim");
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+
     // Wait a bit for rewards to accumulate
     await new Promise((resolve) => setTimeout(resolve, 2000));
 
+    const before = await getAccount(provider.connection, ctx.userReward);
     const tx = await program.methods
       .claimRewards()
-      .accounts({
-        pool: poolPDA,
-        user: userPDA,
-        authority: provider.wallet.publicKey,
-      })
+      .accounts(ctx.claimAccounts)
       .rpc();
 
     console.log("Claim rewards transaction signature", tx);
 
+    const after = await getAccount(provider.connection, ctx.userReward);
+    assert.isTrue(after.amount > before.amount);
     console.log("✅ Rewards claimed successfully");
-    console.log("   - Note: Check logs for reward amount");
+  });
+
+  it("Treats a repeat claim in the same slot as a no-op", async () => {
+    const ctx = await createTokenPool("repeat-claim");
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 1500));
+
+    const claim = () =>
+      program.methods
+        .claimRewards()
+        .accounts(ctx.claimAccounts)
+        .instruction();
+
+    // Both claims land in one transaction, so the second sees zero elapsed time
+    const tx = new anchor.web3.Transaction().add(await claim(), await claim());
+    await provider.sendAndConfirm(tx);
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.isAbove(user.lastRewardClaimTimestamp.toNumber(), 0);
   });
 
   it("Fails to unstake during lock period for locked stakes", async () => {
@@ -210,7 +486,7 @@ describe("wave_stake", () => {
     try {
       // This should work
       await program.methods
-        .stake(amount, lockType)
+        .stake(amount, lockType, null)
         .accounts({
           pool: poolPDA,
           user: lockUserPDA,
@@ -248,12 +524,20 @@ describe("wave_stake", () => {
       .updatePool(
         newRewardPerSecond,
         null, // Keep same lock duration
-        null  // Keep same bonus percentage
+        null, // Keep same bonus percentage
+        null, // Mints are immutable
+        null,
+        null,
+        null, // Keep same lock tiers
+        null,
+        null,
+        null
       )
       .accounts({
         pool: poolPDA,
         authority: authority.publicKey,
       })
+      .signers([authority])
       .rpc();
 
     console.log("Update pool transaction signature", tx);
@@ -266,6 +550,50 @@ describe("wave_stake", () => {
     console.log("   - New reward per second:", newRewardPerSecond.toString());
   });
 
+  it("Rejects pool updates from a non-authority signer", async () => {
+    const ctx = await createTokenPool("update-auth");
+    const outsider = Keypair.generate();
+
+    try {
+      await program.methods
+        .updatePool(new anchor.BN(1), null, null, null, null, null, null, null, null, null)
+        .accounts({ pool: ctx.pool, authority: outsider.publicKey })
+        .signers([outsider])
+        .rpc();
+      assert.fail("Non-authority update should fail");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal(pool.rewardPerSecond.toString(), REWARD_PER_SECOND.toString());
+  });
+
+  it("Rejects attempts to change a pool's mints", async () => {
+    const otherMint = Keypair.generate().publicKey;
+    const attempts = [
+      [otherMint, null, null],
+      [null, otherMint, null],
+      [null, null, otherMint],
+    ];
+
+    for (const [stakeMint, lstMint, rewardMint] of attempts) {
+      try {
+        await program.methods
+          .updatePool(null, null, null, stakeMint, lstMint, rewardMint, null, null, null, null)
+          .accounts({
+            pool: poolPDA,
+            authority: authority.publicKey,
+          })
+          .signers([authority])
+          .rpc();
+        assert.fail("Mint change should have been rejected");
+      } catch (err) {
+        assert.include(err.toString(), "InvalidConfiguration");
+      }
+    }
+  });
+
   it("Closes user account", async () => {
     // Unstake all remaining tokens first
     const user = await program.account.user.fetch(userPDA);
@@ -306,6 +634,1706 @@ describe("wave_stake", () => {
     }
   });
 
+  it("Round-trips LST through stake_lst / unstake_lst and credits it with rewards", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("lst", false, 0, true);
+    const ownerLst = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.lstMint, provider.wallet.publicKey
+    );
+    const lstVault = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.lstMint, ctx.pool, true
+    );
+    await program.methods
+      .stake(new anchor.BN(50 * 1e6), 0, null)
+      .accounts({ ...ctx.stakeAccounts, lstMint: ctx.lstMint, userLstAccount: ownerLst.address })
+      .rpc();
+
+    // Hand the LST to a holder with no principal in the pool
+    const holder = await fundedKeypair();
+    const holderLst = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.lstMint, holder.publicKey
+    );
+    await transfer(provider.connection, payer, ownerLst.address, holderLst.address, payer, 40 * 1e6);
+
+    const { user: holderUser } = poolAddresses("lst", holder.publicKey);
+    await openUserAccount(ctx.pool, holderUser, holder);
+
+    const amount = new anchor.BN(40 * 1e6);
+    const previewedUnderlying = await program.methods
+      .previewRedeem(amount)
+      .accounts({ pool: ctx.pool })
+      .view();
+    const previewedLst = await program.methods
+      .previewDeposit(previewedUnderlying)
+      .accounts({ pool: ctx.pool })
+      .view();
+    assert.equal(previewedLst.toString(), amount.toString());
+
+    const lstAccounts = {
+      pool: ctx.pool,
+      user: holderUser,
+      lstMint: ctx.lstMint,
+      lstVault: lstVault.address,
+      userLstAccount: holderLst.address,
+      authority: holder.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .stakeLst(amount)
+      .accounts(lstAccounts)
+      .signers([holder])
+      .rpc();
+
+    let user = await program.account.user.fetch(holderUser);
+    assert.equal(user.lstStakedUnderlying.toString(), amount.toString());
+    assert.equal(user.lstStakedUnderlying.toString(), previewedUnderlying.toString());
+
+    // The restaked LST is only recorded on the user account, so it can't be closed yet
+    try {
+      await program.methods
+        .closeUserAccount()
+        .accounts({
+          pool: ctx.pool,
+          user: holderUser,
+          userWallet: holder.publicKey,
+          authority: holder.publicKey,
+          stakeMint: ctx.stakeMint,
+          stakeVault: null,
+          userTokenAccount: null,
+          tokenProgram: null,
+          lstMint: null,
+          userLstAccount: null,
+        })
+        .signers([holder])
+        .rpc();
+      assert.fail("Close should fail while LST is restaked");
+    } catch (err) {
+      assert.include(err.toString(), "LstStillStaked");
+    }
+
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    // The pool PDA releases the LST; the pool authority doesn't sign
+    await program.methods
+      .unstakeLst(amount)
+      .accounts(lstAccounts)
+      .signers([holder])
+      .rpc();
+
+    user = await program.account.user.fetch(holderUser);
+    const pool = await program.account.pool.fetch(ctx.pool);
+    const balance = await getAccount(provider.connection, holderLst.address);
+    const vault = await getAccount(provider.connection, lstVault.address);
+    assert.equal(user.lstStakedUnderlying.toNumber(), 0);
+    assert.isTrue(user.rewardsEarned.gtn(0), "restaked LST should earn rewards");
+    assert.equal(pool.totalLstStaked.toNumber(), 0);
+    assert.equal(balance.amount.toString(), amount.toString());
+    assert.equal(vault.amount.toString(), "0");
+  });
+
+  it("Keeps existing locked terms when the pool's lock settings change", async () => {
+    const staker = await fundedKeypair();
+    const [stakerPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user"), poolId, staker.publicKey.toBuffer()],
+      program.programId
+    );
+    await openUserAccount(poolPDA, stakerPDA, staker);
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts({
+        pool: poolPDA,
+        user: stakerPDA,
+        payer: staker.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([staker])
+      .rpc();
+
+    const before = await program.account.user.fetch(stakerPDA);
+
+    await program.methods
+      .updatePool(
+        null,
+        LOCK_DURATION.muln(2),
+        LOCK_BONUS_PERCENTAGE * 2,
+        null,
+        null,
+        null,
+        null,
+        null,
+        null,
+        null
+      )
+      .accounts({
+        pool: poolPDA,
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    const after = await program.account.user.fetch(stakerPDA);
+    assert.equal(after.tierIndex, 1);
+    assert.equal(after.bonusMultiplier, before.bonusMultiplier);
+    assert.equal(
+      after.lockEndTimestamp.toString(),
+      before.lockEndTimestamp.toString()
+    );
+  });
+
+  it("Caps claimable rewards at the pool's reward reserve", async () => {
+    const ctx = await createTokenPool("reserve");
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    // Fund far less than the pool emits per second
+    await program.methods
+      .depositRewards(new anchor.BN(10), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const claimable = await program.methods
+      .claimableRewards()
+      .accounts({
+        pool: ctx.pool,
+        user: ctx.user,
+        owner: provider.wallet.publicKey,
+      })
+      .view();
+
+    assert.equal(claimable.toString(), "10");
+  });
+
+  it("Sweeps foreign tokens from a pool-owned account", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("sweep");
+
+    const foreignMint = await createMint(provider.connection, payer, payer.publicKey, null, 6);
+    const poolForeign = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, foreignMint, ctx.pool, true
+    );
+    const authorityForeign = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, foreignMint, authority.publicKey
+    );
+    await mintTo(provider.connection, payer, foreignMint, poolForeign.address, payer, 5 * 1e6);
+
+    // Swept tokens can only go to the pool authority
+    const strangerForeign = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, foreignMint, provider.wallet.publicKey
+    );
+    try {
+      await program.methods
+        .sweepForeignTokens(foreignMint, new anchor.BN(5 * 1e6))
+        .accounts({
+          pool: ctx.pool,
+          foreignMint,
+          poolTokenAccount: poolForeign.address,
+          destinationTokenAccount: strangerForeign.address,
+          authority: authority.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
+      assert.fail("Sweeping to an account the authority doesn't own should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidTokenAccount");
+    }
+
+    await program.methods
+      .sweepForeignTokens(foreignMint, new anchor.BN(5 * 1e6))
+      .accounts({
+        pool: ctx.pool,
+        foreignMint,
+        poolTokenAccount: poolForeign.address,
+        destinationTokenAccount: authorityForeign.address,
+        authority: authority.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([authority])
+      .rpc();
+
+    const swept = await getAccount(provider.connection, authorityForeign.address);
+    assert.equal(swept.amount.toString(), (5 * 1e6).toString());
+
+    // The reward vault's mint is off limits
+    try {
+      await program.methods
+        .sweepForeignTokens(ctx.rewardMint, new anchor.BN(1))
+        .accounts({
+          pool: ctx.pool,
+          foreignMint: ctx.rewardMint,
+          poolTokenAccount: ctx.rewardVault,
+          destinationTokenAccount: ctx.authorityDepositAccounts.depositorTokenAccount,
+          authority: authority.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
+      assert.fail("Sweeping the reward mint should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+  });
+
+  it("Rejects SPL stake and unstake with missing optional accounts", async () => {
+    const ctx = await createTokenPool("missing");
+    const cases: [string, string][] = [
+      ["userTokenAccount", "InvalidTokenAccount"],
+      ["stakeVault", "InvalidStakeVault"],
+      ["tokenProgram", "InvalidTokenProgram"],
+    ];
+
+    for (const [missing, expected] of cases) {
+      try {
+        await program.methods
+          .stake(new anchor.BN(1e6), 0, null)
+          .accounts({ ...ctx.stakeAccounts, [missing]: null })
+          .rpc();
+        assert.fail(`Stake without ${missing} should fail`);
+      } catch (err) {
+        assert.include(err.toString(), expected);
+      }
+    }
+
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const { payer, ...unstakeAccounts } = ctx.stakeAccounts;
+    for (const [missing, expected] of cases) {
+      try {
+        await program.methods
+          .unstake(new anchor.BN(1e6))
+          .accounts({ ...unstakeAccounts, authority: payer, [missing]: null })
+          .rpc();
+        assert.fail(`Unstake without ${missing} should fail`);
+      } catch (err) {
+        assert.include(err.toString(), expected);
+      }
+    }
+  });
+
+  it("Deposits rewards in installments and extends the emission end", async () => {
+    const ctx = await createTokenPool("installments");
+
+    // Only the pool authority can put an end on an open-ended pool
+    try {
+      await program.methods
+        .depositRewards(REWARD_PER_SECOND.muln(1), true)
+        .accounts(ctx.depositAccounts)
+        .rpc();
+      assert.fail("A non-authority deposit must not end open-ended emissions");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    await program.methods
+      .depositRewards(REWARD_PER_SECOND.muln(100), true)
+      .accounts(ctx.authorityDepositAccounts)
+      .signers([authority])
+      .rpc();
+    const first = await program.account.pool.fetch(ctx.pool);
+    assert.equal(first.rewardReserve.toString(), REWARD_PER_SECOND.muln(100).toString());
+
+    // Once an end exists, any depositor can fund it further
+    await program.methods
+      .depositRewards(REWARD_PER_SECOND.muln(50), true)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    const second = await program.account.pool.fetch(ctx.pool);
+
+    assert.equal(second.rewardReserve.toString(), REWARD_PER_SECOND.muln(150).toString());
+    assert.equal(second.totalRewardFunded.toString(), REWARD_PER_SECOND.muln(150).toString());
+    assert.equal(
+      second.rewardEndTimestamp.toNumber(),
+      first.rewardEndTimestamp.toNumber() + 50
+    );
+
+    const vault = await getAccount(provider.connection, ctx.rewardVault);
+    assert.equal(vault.amount.toString(), REWARD_PER_SECOND.muln(150).toString());
+  });
+
+  it("Returns the pool's lock tiers", async () => {
+    const ctx = await createTokenPool("tiers");
+
+    const tiers = await program.methods
+      .getLockTiers()
+      .accounts({ pool: ctx.pool })
+      .view();
+
+    assert.equal(tiers.length, 2);
+    assert.equal(tiers[0].duration.toNumber(), 0);
+    assert.equal(tiers[0].bonusBps, 0);
+    assert.equal(tiers[1].duration.toString(), LOCK_DURATION.toString());
+    assert.equal(tiers[1].bonusBps, LOCK_BONUS_PERCENTAGE);
+  });
+
+  it("Splits claimed rewards with the protocol treasury", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("protocol-fee");
+    const treasury = Keypair.generate().publicKey;
+    const treasuryReward = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.rewardMint, treasury
+    );
+
+    await program.methods
+      .setRewardProtocolFee(500, treasury)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .setRewardProtocolFee(5000, treasury)
+        .accounts({ pool: ctx.pool, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      assert.fail("A fee above the maximum should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    try {
+      await program.methods
+        .setRewardProtocolFee(500, PublicKey.default)
+        .accounts({ pool: ctx.pool, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      assert.fail("A fee without a recipient should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    // Keep emissions small enough for the vault to cover
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    let listener: number | undefined;
+    const event = new Promise<any>((resolve) => {
+      listener = program.addEventListener("rewardsClaimed", (e) => resolve(e));
+    });
+
+    const userBefore = await getAccount(provider.connection, ctx.userReward);
+    await program.methods
+      .claimRewards()
+      .accounts({ ...ctx.claimAccounts, protocolFeeAccount: treasuryReward.address })
+      .rpc();
+
+    const claimed = await event;
+    await program.removeEventListener(listener!);
+
+    const gross = claimed.amount.add(claimed.protocolFee);
+    assert.equal(claimed.protocolFee.toString(), gross.muln(500).divn(10000).toString());
+    assert.equal(claimed.protocolFeeRecipient.toString(), treasury.toString());
+
+    const userAfter = await getAccount(provider.connection, ctx.userReward);
+    const treasuryAfter = await getAccount(provider.connection, treasuryReward.address);
+    assert.equal((userAfter.amount - userBefore.amount).toString(), claimed.amount.toString());
+    assert.equal(treasuryAfter.amount.toString(), claimed.protocolFee.toString());
+  });
+
+  it("Routes the unstake fee into the reward reserve", async () => {
+    const ctx = await createTokenPool("unstake-fee", true);
+
+    await program.methods
+      .setUnstakeFee(1000)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const viewAccounts = {
+      pool: ctx.pool,
+      user: ctx.user,
+      owner: provider.wallet.publicKey,
+    };
+    const before = await program.methods.claimableRewards().accounts(viewAccounts).view();
+    const walletBefore = await getAccount(provider.connection, ctx.userStake);
+
+    const { payer, ...unstakeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .unstake(new anchor.BN(50 * 1e6))
+      .accounts({ ...unstakeAccounts, authority: payer, rewardVault: ctx.rewardVault })
+      .rpc();
+
+    const walletAfter = await getAccount(provider.connection, ctx.userStake);
+    assert.equal((walletAfter.amount - walletBefore.amount).toString(), (45 * 1e6).toString());
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal(pool.rewardReserve.toString(), (5 * 1e6).toString());
+
+    // The remaining stake can now draw on the fee
+    const after = await program.methods.claimableRewards().accounts(viewAccounts).view();
+    assert.isTrue(after.gt(before));
+  });
+
+  it("Rejects a stake mint that does not match the pool", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("wrong-mint");
+    const otherMint = await createMint(provider.connection, payer, payer.publicKey, null, 6);
+
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), 0, null)
+        .accounts({ ...ctx.stakeAccounts, stakeMint: otherMint })
+        .rpc();
+      assert.fail("Staking with a foreign mint should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const { payer: owner, ...unstakeAccounts } = ctx.stakeAccounts;
+    try {
+      await program.methods
+        .unstake(new anchor.BN(1e6))
+        .accounts({ ...unstakeAccounts, authority: owner, stakeMint: otherMint })
+        .rpc();
+      assert.fail("Unstaking with a foreign mint should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+  });
+
+  it("Lets locked stakers exit during global emergency mode", async () => {
+    const ctx = await createTokenPool("emergency");
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    const walletBefore = await getAccount(provider.connection, ctx.userStake);
+
+    const { payer, systemProgram, ...exitAccounts } = ctx.stakeAccounts;
+    const emergencyAccounts = { ...exitAccounts, globalState: globalStatePDA, authority: payer };
+
+    try {
+      await program.methods
+        .emergencyExit()
+        .accounts(emergencyAccounts)
+        .rpc();
+      assert.fail("Emergency exit should require emergency mode");
+    } catch (err) {
+      assert.include(err.toString(), "NotInEmergency");
+    }
+
+    await program.methods
+      .setGlobalPaused(true)
+      .accounts({ globalState: globalStatePDA, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .emergencyExit()
+        .accounts(emergencyAccounts)
+        .rpc();
+    } finally {
+      await program.methods
+        .setGlobalPaused(false)
+        .accounts({ globalState: globalStatePDA, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    }
+
+    const user = await program.account.user.fetch(ctx.user);
+    const walletAfter = await getAccount(provider.connection, ctx.userStake);
+    assert.equal(user.amount.toNumber(), 0);
+    assert.equal((walletAfter.amount - walletBefore.amount).toString(), (10 * 1e6).toString());
+  });
+
+  it("Pokes several pools in one batch", async () => {
+    const first = await createTokenPool("batch-a");
+    const second = await createTokenPool("batch-b");
+    const pools = [first.pool, second.pool];
+
+    const before = await Promise.all(pools.map((p) => program.account.pool.fetch(p)));
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const remainingAccounts = [
+      ...pools.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false })),
+      // Not a pool; skipped rather than failing the batch
+      { pubkey: first.stakeMint, isWritable: true, isSigner: false },
+    ];
+
+    const updated = await program.methods
+      .pokePoolsBatch()
+      .accounts({ keeper: provider.wallet.publicKey })
+      .remainingAccounts(remainingAccounts)
+      .view();
+    assert.equal(updated, 2);
+
+    await program.methods
+      .pokePoolsBatch()
+      .accounts({ keeper: provider.wallet.publicKey })
+      .remainingAccounts(remainingAccounts)
+      .rpc();
+
+    const after = await Promise.all(pools.map((p) => program.account.pool.fetch(p)));
+    after.forEach((pool, i) => {
+      assert.isTrue(pool.lastUpdateTimestamp.gt(before[i].lastUpdateTimestamp));
+    });
+  });
+
+  it("Reports whether a pool is emitting", async () => {
+    const ctx = await createTokenPool("emitting");
+
+    let status = await program.methods
+      .isPoolEmitting()
+      .accounts({ pool: ctx.pool })
+      .view();
+    assert.isTrue(status.emitting);
+
+    // Fund a short window and let it run out
+    await program.methods
+      .depositRewards(REWARD_PER_SECOND.muln(2), true)
+      .accounts(ctx.authorityDepositAccounts)
+      .signers([authority])
+      .rpc();
+    status = await program.methods.isPoolEmitting().accounts({ pool: ctx.pool }).view();
+    assert.isTrue(status.emitting);
+    assert.isTrue(status.secondsRemaining.lten(2));
+
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    status = await program.methods.isPoolEmitting().accounts({ pool: ctx.pool }).view();
+    assert.isFalse(status.emitting);
+    assert.equal(status.secondsRemaining.toNumber(), 0);
+  });
+
+  it("Rejects a lock schedule that is not ascending", async () => {
+    const { id, pool } = poolAddresses("zero-lock");
+
+    // A zero-length lock tier would collide with the flexible tier
+    try {
+      await openPool(id, pool, STAKE_MINT, LST_MINT, REWARD_MINT, {
+        lockDuration: new anchor.BN(0),
+      });
+      assert.fail("A zero lock duration should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    const ctx = await createTokenPool("tier-order");
+    try {
+      await program.methods
+        .updatePool(null, new anchor.BN(0), null, null, null, null, null, null, null, null)
+        .accounts({ pool: ctx.pool, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      assert.fail("A zero lock duration should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    await program.methods
+      .updatePool(null, LOCK_DURATION.muln(3), LOCK_BONUS_PERCENTAGE + 1000, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+  });
+
+  it("Rejects a claim routed through a program other than SPL Token", async () => {
+    const ctx = await createTokenPool("claim-wrong-program");
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+
+    try {
+      await program.methods
+        .claimRewards()
+        .accounts({ ...ctx.claimAccounts, tokenProgram: SystemProgram.programId })
+        .rpc();
+      assert.fail("A substituted token program must not receive the pool's signature");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidProgramId");
+    }
+  });
+
+  it("Pays claimed rewards out of the reward vault", async () => {
+    const ctx = await createTokenPool("vault-claim");
+
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    // Nothing deposited yet, so the vault can't cover the claim
+    try {
+      await program.methods.claimRewards().accounts(ctx.claimAccounts).rpc();
+      assert.fail("Claiming from an empty vault should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientLiquidity");
+    }
+
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+
+    const vaultBefore = await getAccount(provider.connection, ctx.rewardVault);
+    const userBefore = await getAccount(provider.connection, ctx.userReward);
+    await program.methods.claimRewards().accounts(ctx.claimAccounts).rpc();
+    const vaultAfter = await getAccount(provider.connection, ctx.rewardVault);
+    const userAfter = await getAccount(provider.connection, ctx.userReward);
+
+    const paid = vaultBefore.amount - vaultAfter.amount;
+    assert.isTrue(paid > BigInt(0));
+    assert.equal((userAfter.amount - userBefore.amount).toString(), paid.toString());
+  });
+
+  it("Funds the reward vault without moving the emission end", async () => {
+    const ctx = await createTokenPool("fund-vault");
+    const fundAccounts = {
+      pool: ctx.pool,
+      rewardMint: ctx.rewardMint,
+      rewardVault: ctx.rewardVault,
+      funderTokenAccount: ctx.userReward,
+      funder: provider.wallet.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    // The funder's account must hold the reward mint
+    try {
+      await program.methods
+        .fundRewardVault(new anchor.BN(1e6))
+        .accounts({ ...fundAccounts, funderTokenAccount: ctx.userStake })
+        .rpc();
+      assert.fail("Funding from another mint should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+
+    const before = await program.account.pool.fetch(ctx.pool);
+    const amount = new anchor.BN(25 * 1e6);
+    await program.methods.fundRewardVault(amount).accounts(fundAccounts).rpc();
+
+    const after = await program.account.pool.fetch(ctx.pool);
+    const vault = await getAccount(provider.connection, ctx.rewardVault);
+    assert.equal(after.rewardReserve.sub(before.rewardReserve).toString(), amount.toString());
+    assert.equal(after.totalRewardFunded.sub(before.totalRewardFunded).toString(), amount.toString());
+    assert.equal(after.rewardEndTimestamp.toString(), before.rewardEndTimestamp.toString());
+    assert.equal(vault.amount.toString(), amount.toString());
+  });
+
+  it("Reconciles a drifted total_staked with the stake vault", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("reconcile");
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    // Tokens arriving outside `stake` put the vault out of step with the pool
+    await mintTo(provider.connection, payer, ctx.stakeMint, ctx.stakeVault, payer, 5 * 1e6);
+    const vault = await getAccount(provider.connection, ctx.stakeVault);
+
+    const reconcile = (actual: anchor.BN, stakeVault = ctx.stakeVault) =>
+      program.methods
+        .reconcileTotalStaked(actual)
+        .accounts({
+          pool: ctx.pool,
+          stakeVault,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+
+    try {
+      await reconcile(new anchor.BN(1));
+      assert.fail("An amount other than the vault balance should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidAmount");
+    }
+
+    // The authority can't pick an account it controls to set an arbitrary total
+    await mintTo(provider.connection, payer, ctx.stakeMint, ctx.poolAuthorityStake, payer, 1);
+    try {
+      await reconcile(new anchor.BN(1), ctx.poolAuthorityStake);
+      assert.fail("Reconciling against another account should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidStakeVault");
+    }
+
+    await reconcile(new anchor.BN(vault.amount.toString()));
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal(pool.totalStaked.toString(), (15 * 1e6).toString());
+  });
+
+  it("Claims part of the pending rewards and leaves the rest", async () => {
+    const ctx = await createTokenPool("partial-claim");
+
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    try {
+      await program.methods.claimRewardsPartial(10001).accounts(ctx.claimAccounts).rpc();
+      assert.fail("More than 100% should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidAmount");
+    }
+
+    const userBefore = await getAccount(provider.connection, ctx.userReward);
+    await program.methods.claimRewardsPartial(5000).accounts(ctx.claimAccounts).rpc();
+    const userAfter = await getAccount(provider.connection, ctx.userReward);
+    const paid = userAfter.amount - userBefore.amount;
+
+    // Half was paid, the other half is still owed
+    const user = await program.account.user.fetch(ctx.user);
+    assert.isTrue(paid > BigInt(0));
+    assert.isTrue(BigInt(user.rewardsEarned.toString()) >= paid);
+
+    const claimable = await program.methods
+      .claimableRewards()
+      .accounts({ pool: ctx.pool, user: ctx.user, owner: provider.wallet.publicKey })
+      .view();
+    assert.isTrue(claimable.gte(user.rewardsEarned));
+  });
+
+  it("Returns the remaining stake when a user account is closed", async () => {
+    const ctx = await createTokenPool("close-refund");
+
+    // No emissions, so nothing is left to claim at close
+    await program.methods
+      .updatePool(new anchor.BN(0), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .stake(new anchor.BN(25 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    const walletBefore = await getAccount(provider.connection, ctx.userStake);
+
+    const { payer, systemProgram, ...closeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .closeUserAccount()
+      .accounts({ ...closeAccounts, userWallet: payer, authority: payer })
+      .rpc();
+
+    const walletAfter = await getAccount(provider.connection, ctx.userStake);
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal((walletAfter.amount - walletBefore.amount).toString(), (25 * 1e6).toString());
+    assert.equal(pool.totalStaked.toNumber(), 0);
+    assert.isNull(await program.account.user.fetchNullable(ctx.user));
+  });
+
+  it("Refuses to close an account with unclaimed rewards", async () => {
+    const ctx = await createTokenPool("close-unclaimed");
+
+    await program.methods
+      .stake(new anchor.BN(25 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 1500));
+
+    const { payer, systemProgram, ...closeAccounts } = ctx.stakeAccounts;
+    try {
+      await program.methods
+        .closeUserAccount()
+        .accounts({ ...closeAccounts, userWallet: payer, authority: payer })
+        .rpc();
+      assert.fail("Close should fail while rewards are unclaimed");
+    } catch (err) {
+      assert.include(err.toString(), "UnclaimedRewards");
+    }
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.amount.toNumber(), 25 * 1e6);
+  });
+
+  it("Stakes and unstakes native SOL through the pool PDA", async () => {
+    const { id, pool, user } = poolAddresses("native-sol");
+    await openPool(id, pool, NATIVE_MINT);
+    await openUserAccount(pool, user);
+
+    const amount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 2);
+    const poolLamportsBefore = await provider.connection.getBalance(pool);
+
+    await program.methods
+      .stake(amount, 0, null)
+      .accounts({
+        pool,
+        user,
+        stakeMint: NATIVE_MINT,
+        stakeVault: null,
+        userTokenAccount: null,
+        payer: provider.wallet.publicKey,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    assert.equal(
+      await provider.connection.getBalance(pool),
+      poolLamportsBefore + amount.toNumber()
+    );
+
+    await program.methods
+      .unstake(amount)
+      .accounts({
+        pool,
+        user,
+        stakeMint: NATIVE_MINT,
+        stakeVault: null,
+        userTokenAccount: null,
+        authority: provider.wallet.publicKey,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
+        rewardVault: null,
+      })
+      .rpc();
+
+    // Only the staked lamports leave; the pool stays rent-exempt
+    assert.equal(await provider.connection.getBalance(pool), poolLamportsBefore);
+    const position = await program.account.user.fetch(user);
+    assert.equal(position.amount.toNumber(), 0);
+  });
+
+  it("Charges the early-unstake penalty on locked stakes", async () => {
+    const ctx = await createTokenPool("early-unstake", false, 1000);
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    const walletBefore = await getAccount(provider.connection, ctx.userStake);
+
+    const { payer, systemProgram, ...unstakeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .emergencyUnstake(new anchor.BN(10 * 1e6))
+      .accounts({
+        ...unstakeAccounts,
+        authority: payer,
+        // Pool has no protocol fee recipient, so the penalty goes to the pool authority
+        penaltyRecipient: ctx.poolAuthorityStake,
+      })
+      .rpc();
+
+    const user = await program.account.user.fetch(ctx.user);
+    const pool = await program.account.pool.fetch(ctx.pool);
+    const walletAfter = await getAccount(provider.connection, ctx.userStake);
+    assert.equal(user.amount.toNumber(), 0);
+    assert.equal(pool.totalStaked.toNumber(), 0);
+    assert.equal((walletAfter.amount - walletBefore.amount).toString(), (9 * 1e6).toString());
+  });
+
+  it("Returns a user's whole position in one view", async () => {
+    const ctx = await createTokenPool("position");
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const viewAccounts = { pool: ctx.pool, user: ctx.user, owner: provider.wallet.publicKey };
+    const position = await program.methods.getUserPosition().accounts(viewAccounts).view();
+    const claimable = await program.methods.claimableRewards().accounts(viewAccounts).view();
+    const user = await program.account.user.fetch(ctx.user);
+
+    assert.equal(position.amount.toString(), user.amount.toString());
+    assert.equal(position.lockType, user.lockType);
+    assert.equal(position.lockEndTimestamp.toString(), user.lockEndTimestamp.toString());
+    assert.equal(position.multiplierBps.toNumber(), user.bonusMultiplier);
+    assert.isTrue(claimable.gte(position.claimableRewards)); // Read a moment later
+    assert.isTrue(position.pendingRewards.gte(position.claimableRewards));
+  });
+
+  it("Stakes into a configured lock tier", async () => {
+    const ctx = await createTokenPool("lock-tiers");
+    const week = 7 * 24 * 60 * 60;
+    const tiers = [
+      { duration: new anchor.BN(0), bonusBps: 0 },
+      { duration: new anchor.BN(week), bonusBps: 1000 },
+      { duration: new anchor.BN(12 * week), bonusBps: 10000 },
+    ];
+
+    await program.methods
+      .updatePool(null, null, null, null, null, null, tiers, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    // Editing only the legacy lock fields would replace the custom tiers
+    try {
+      await program.methods
+        .updatePool(null, new anchor.BN(week), null, null, null, null, null, null, null, null)
+        .accounts({ pool: ctx.pool, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      assert.fail("Legacy lock edits should fail on a pool with custom tiers");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), tiers.length, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+      assert.fail("Staking into an undefined tier should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidLockType");
+    }
+
+    await program.methods
+      .stake(new anchor.BN(1e6), 2, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.tierIndex, 2);
+    assert.equal(user.lockType, 1);
+    assert.equal(user.bonusMultiplier, 20000);
+    assert.equal(
+      user.lockEndTimestamp.sub(user.lockStartTimestamp).toNumber(),
+      12 * week
+    );
+  });
+
+  it("Sets a sorted amount multiplier curve", async () => {
+    const ctx = await createTokenPool("amount-curve");
+    const point = (threshold: number, multiplierBps: number) => ({
+      threshold: new anchor.BN(threshold),
+      multiplierBps,
+    });
+
+    try {
+      await program.methods
+        .setAmountMultiplierCurve([point(500 * 1e6, 12000), point(0, 9000)])
+        .accounts({ pool: ctx.pool, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      assert.fail("Unsorted curve should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidConfiguration");
+    }
+
+    await program.methods
+      .setAmountMultiplierCurve([point(0, 9000), point(500 * 1e6, 12000)])
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal(pool.amountMultiplierCurve.length, 2);
+    assert.equal(pool.amountMultiplierCurve[1].multiplierBps, 12000);
+  });
+
+  it("Extends the unlock time when adding to a locked stake", async () => {
+    const ctx = await createTokenPool("lock-top-up");
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    const before = await program.account.user.fetch(ctx.user);
+
+    // Top-ups must stay in the position's tier
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), 0, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+      assert.fail("Flexible top-up of a locked stake should fail");
+    } catch (err) {
+      assert.include(err.toString(), "LockTierMismatch");
+    }
+
+    await new Promise((resolve) => setTimeout(resolve, 1500));
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 1, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const after = await program.account.user.fetch(ctx.user);
+    assert.equal(after.amount.toNumber(), 20 * 1e6);
+    assert.equal(after.lockType, 1);
+    assert.equal(after.bonusMultiplier, before.bonusMultiplier);
+    assert.isTrue(after.lockEndTimestamp.gt(before.lockEndTimestamp));
+    assert.equal(after.lockStartTimestamp.toString(), before.lockStartTimestamp.toString());
+  });
+
+  it("Settles the reward accumulator before a rate change", async () => {
+    const ctx = await createTokenPool("rate-change");
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    await program.methods
+      .updatePool(new anchor.BN(0), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const settled = await program.account.pool.fetch(ctx.pool);
+    assert.isTrue(settled.rewardPerTokenStored.gtn(0));
+
+    // With the rate at zero, nothing more accrues after the change
+    const viewAccounts = { pool: ctx.pool, user: ctx.user, owner: provider.wallet.publicKey };
+    const before = await program.methods.getUserPosition().accounts(viewAccounts).view();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    const after = await program.methods.getUserPosition().accounts(viewAccounts).view();
+    assert.isTrue(before.pendingRewards.gtn(0));
+    assert.equal(after.pendingRewards.toString(), before.pendingRewards.toString());
+  });
+
+  it("Blocks new stakes while a pool is paused but lets users exit", async () => {
+    const ctx = await createTokenPool("paused");
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    const outsider = Keypair.generate();
+    try {
+      await program.methods
+        .setPoolPaused(true)
+        .accounts({ pool: ctx.pool, authority: outsider.publicKey })
+        .signers([outsider])
+        .rpc();
+      assert.fail("Only the pool authority can pause");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    await program.methods
+      .setPoolPaused(true)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), 0, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+      assert.fail("Staking into a paused pool should fail");
+    } catch (err) {
+      assert.include(err.toString(), "PoolPaused");
+    }
+
+    const { payer, ...unstakeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .unstake(new anchor.BN(10 * 1e6))
+      .accounts({ ...unstakeAccounts, authority: payer })
+      .rpc();
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.amount.toNumber(), 0);
+  });
+
+  it("Still pays banked rewards after unstaking to zero", async () => {
+    const ctx = await createTokenPool("claim-after-exit");
+
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const { payer, ...unstakeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .unstake(new anchor.BN(100 * 1e6))
+      .accounts({ ...unstakeAccounts, authority: payer })
+      .rpc();
+
+    const exited = await program.account.user.fetch(ctx.user);
+    assert.equal(exited.amount.toNumber(), 0);
+    assert.isTrue(exited.rewardsEarned.gtn(0));
+
+    const userBefore = await getAccount(provider.connection, ctx.userReward);
+    await program.methods.claimRewards().accounts(ctx.claimAccounts).rpc();
+    const userAfter = await getAccount(provider.connection, ctx.userReward);
+
+    assert.equal(
+      (userAfter.amount - userBefore.amount).toString(),
+      exited.rewardsEarned.toString()
+    );
+  });
+
+  it("Compounds rewards into the stake when both mints match", async () => {
+    const split = await createTokenPool("compound-split");
+    const { userRewardTokenAccount, ...splitCompound } = split.claimAccounts;
+    try {
+      await program.methods
+        .compoundRewards()
+        .accounts({ ...splitCompound, stakeVault: split.stakeVault })
+        .rpc();
+      assert.fail("Compounding a different reward mint should fail");
+    } catch (err) {
+      assert.include(err.toString(), "CannotCompoundDifferentMint");
+    }
+
+    const ctx = await createTokenPool("compound", true);
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const { userRewardTokenAccount: _, ...compoundAccounts } = ctx.claimAccounts;
+    const vaultBefore = await getAccount(provider.connection, ctx.stakeVault);
+    await program.methods
+      .compoundRewards()
+      .accounts({ ...compoundAccounts, stakeVault: ctx.stakeVault })
+      .rpc();
+    const vaultAfter = await getAccount(provider.connection, ctx.stakeVault);
+
+    const user = await program.account.user.fetch(ctx.user);
+    const compounded = user.amount.sub(new anchor.BN(100 * 1e6));
+    assert.isTrue(compounded.gtn(0));
+    assert.equal((vaultAfter.amount - vaultBefore.amount).toString(), compounded.toString());
+    assert.equal(user.rewardsEarned.toNumber(), 0);
+  });
+
+  it("Auto-compounds rewards into the position on the next stake", async () => {
+    const ctx = await createTokenPool("auto-compound", true);
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .depositRewards(new anchor.BN(500 * 1e6), false)
+      .accounts(ctx.depositAccounts)
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await program.methods
+      .setRewardMode(1)
+      .accounts({ pool: ctx.pool, user: ctx.user, authority: provider.wallet.publicKey })
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts({ ...ctx.stakeAccounts, rewardVault: ctx.rewardVault })
+      .rpc();
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.rewardMode, 1);
+    assert.isTrue(user.amount.gt(new anchor.BN(110 * 1e6)));
+    assert.equal(user.rewardsEarned.toNumber(), 0);
+  });
+
+  it("Mints LST on stake and burns it on unstake", async () => {
+    const ctx = await createTokenPool("lst-mint", false, 0, true);
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const userLst = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.lstMint, provider.wallet.publicKey
+    );
+    const lstAccounts = { lstMint: ctx.lstMint, userLstAccount: userLst.address };
+
+    await program.methods
+      .stake(new anchor.BN(100 * 1e6), 0, null)
+      .accounts({ ...ctx.stakeAccounts, ...lstAccounts })
+      .rpc();
+    let lst = await getAccount(provider.connection, userLst.address);
+    assert.equal(lst.amount.toString(), (100 * 1e6).toString());
+
+    const { payer: _, ...unstakeAccounts } = ctx.stakeAccounts;
+    await program.methods
+      .unstake(new anchor.BN(40 * 1e6))
+      .accounts({ ...unstakeAccounts, authority: provider.wallet.publicKey, ...lstAccounts })
+      .rpc();
+    lst = await getAccount(provider.connection, userLst.address);
+    assert.equal(lst.amount.toString(), (60 * 1e6).toString());
+
+    // The outstanding LST must be burned before the rest of the principal leaves
+    try {
+      await program.methods
+        .unstake(new anchor.BN(60 * 1e6))
+        .accounts({ ...unstakeAccounts, authority: provider.wallet.publicKey })
+        .rpc();
+      assert.fail("Unstaking without burning the LST should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+
+    // An LST mint the pool PDA cannot mint from is rejected
+    const foreignLst = await createMint(provider.connection, payer, payer.publicKey, null, 6);
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), 0, null)
+        .accounts({ ...ctx.stakeAccounts, lstMint: foreignLst, userLstAccount: userLst.address })
+        .rpc();
+      assert.fail("A mint not controlled by the pool should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.lstMinted.toString(), (60 * 1e6).toString());
+  });
+
+  it("Previews pending rewards without touching any account", async () => {
+    const ctx = await createTokenPool("pending-view");
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+
+    const viewAccounts = { pool: ctx.pool, user: ctx.user, owner: provider.wallet.publicKey };
+    const userBefore = await provider.connection.getAccountInfo(ctx.user);
+    const poolBefore = await provider.connection.getAccountInfo(ctx.pool);
+    const pending = await program.methods.viewPendingRewards().accounts(viewAccounts).view();
+    const position = await program.methods.getUserPosition().accounts(viewAccounts).view();
+
+    assert.isTrue(pending.gtn(0));
+    assert.isTrue(position.pendingRewards.gte(pending)); // Read a moment later
+    assert.isTrue(
+      (await provider.connection.getAccountInfo(ctx.user)).data.equals(userBefore.data)
+    );
+    assert.isTrue(
+      (await provider.connection.getAccountInfo(ctx.pool)).data.equals(poolBefore.data)
+    );
+  });
+
+  it("Boosts stakers who also hold a position in the paired pool", async () => {
+    const wave = await createTokenPool("pair-wave");
+    const wealth = await createTokenPool("pair-wealth");
+    await program.methods
+      .setPairedPool(wealth.pool, 5000)
+      .accounts({ pool: wave.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts(wave.stakeAccounts)
+      .rpc();
+
+    const verifyAccounts = {
+      pool: wave.pool,
+      user: wave.user,
+      pairedPool: wealth.pool,
+      pairedUser: wealth.user,
+      owner: provider.wallet.publicKey,
+    };
+    const viewAccounts = { pool: wave.pool, user: wave.user, owner: provider.wallet.publicKey };
+    // Settlements re-check the bonus against the paired position passed alongside
+    const pairedPosition = [{ pubkey: wealth.user, isSigner: false, isWritable: false }];
+    const verify = () => program.methods.verifyPairedStake().accounts(verifyAccounts).rpc();
+    const pairBonus = async () => {
+      const position = await program.methods
+        .getUserPosition()
+        .accounts(viewAccounts)
+        .remainingAccounts(pairedPosition)
+        .view();
+      return position.pairBonusBps.toNumber();
+    };
+
+    // No stake in the paired pool yet
+    await verify();
+    assert.equal(await pairBonus(), 0);
+
+    await program.methods
+      .stake(new anchor.BN(5 * 1e6), 0, null)
+      .accounts(wealth.stakeAccounts)
+      .rpc();
+    await verify();
+    assert.equal(await pairBonus(), 5000);
+
+    // A new bonus reaches a verified staker only when they verify again
+    await program.methods
+      .setPairedPool(wealth.pool, 2000)
+      .accounts({ pool: wave.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    assert.equal(await pairBonus(), 5000);
+    await verify();
+    assert.equal(await pairBonus(), 2000);
+
+    // Settling with the paired position still staked keeps the bonus
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, null)
+      .accounts(wave.stakeAccounts)
+      .remainingAccounts(pairedPosition)
+      .rpc();
+    assert.equal((await program.account.user.fetch(wave.user)).pairBonusBps, 2000);
+
+    // Once the paired position is emptied, the next settlement revokes the bonus
+    // without anyone calling verify
+    const { payer: _, ...unstakeAccounts } = wealth.stakeAccounts;
+    await program.methods
+      .unstake(new anchor.BN(5 * 1e6))
+      .accounts({ ...unstakeAccounts, authority: provider.wallet.publicKey })
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, null)
+      .accounts(wave.stakeAccounts)
+      .remainingAccounts(pairedPosition)
+      .rpc();
+    const user = await program.account.user.fetch(wave.user);
+    assert.equal(user.pairBonusBps, 0);
+    assert.isTrue(user.verifiedPairedPool.equals(PublicKey.default));
+  });
+
+  it("Counts stakers only when a position fills or empties", async () => {
+    const ctx = await createTokenPool("staker-count");
+    const stakerCount = async () =>
+      (await program.account.pool.fetch(ctx.pool)).stakerCount.toNumber();
+    assert.equal(await stakerCount(), 0);
+
+    for (const amount of [10, 5]) {
+      await program.methods
+        .stake(new anchor.BN(amount * 1e6), 0, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+    }
+    assert.equal(await stakerCount(), 1);
+
+    const { payer: _, ...unstakeAccounts } = ctx.stakeAccounts;
+    const unstake = (amount: number) =>
+      program.methods
+        .unstake(new anchor.BN(amount * 1e6))
+        .accounts({ ...unstakeAccounts, authority: provider.wallet.publicKey })
+        .rpc();
+    await unstake(5);
+    assert.equal(await stakerCount(), 1);
+    await unstake(10);
+    assert.equal(await stakerCount(), 0);
+  });
+
+  it("Enforces the pool's minimum stake and per-user maximum", async () => {
+    const ctx = await createTokenPool("stake-limits");
+    await program.methods
+      .updatePool(null, null, null, null, null, null, null, new anchor.BN(5 * 1e6), new anchor.BN(20 * 1e6), null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const stake = (amount: anchor.BN) =>
+      program.methods.stake(amount, 0, null).accounts(ctx.stakeAccounts).rpc();
+
+    try {
+      await stake(new anchor.BN(5 * 1e6 - 1));
+      assert.fail("Staking below the minimum should fail");
+    } catch (err) {
+      assert.include(err.toString(), "BelowMinimumStake");
+    }
+
+    // Exactly the minimum, then up to exactly the cap
+    await stake(new anchor.BN(5 * 1e6));
+    await stake(new anchor.BN(15 * 1e6));
+
+    try {
+      await stake(new anchor.BN(5 * 1e6));
+      assert.fail("Staking past the per-user maximum should fail");
+    } catch (err) {
+      assert.include(err.toString(), "ExceedsMaxStake");
+    }
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.amount.toString(), (20 * 1e6).toString());
+  });
+
+  it("Emits the running reward reserve on deposits and claims", async () => {
+    const ctx = await createTokenPool("reserve-events");
+    await program.methods
+      .updatePool(new anchor.BN(1_000), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const updates: any[] = [];
+    const listener = program.addEventListener("rewardReserveUpdated", (e) => updates.push(e));
+
+    for (const amount of [100, 50]) {
+      await program.methods
+        .depositRewards(new anchor.BN(amount * 1e6), false)
+        .accounts(ctx.depositAccounts)
+        .rpc();
+    }
+    await program.methods
+      .stake(new anchor.BN(10 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+    await new Promise((resolve) => setTimeout(resolve, 2000));
+    await program.methods.claimRewards().accounts(ctx.claimAccounts).rpc();
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+
+    assert.equal(updates.length, 3);
+    const [first, second, claim] = updates;
+    assert.equal(first.deposited.toString(), (100 * 1e6).toString());
+    assert.equal(first.rewardReserve.toString(), (100 * 1e6).toString());
+    assert.equal(second.rewardReserve.toString(), (150 * 1e6).toString());
+    assert.equal(second.updatedBy.toString(), provider.wallet.publicKey.toString());
+    assert.isTrue(claim.withdrawn.gtn(0));
+    assert.equal(
+      claim.rewardReserve.toString(),
+      second.rewardReserve.sub(claim.withdrawn).toString()
+    );
+    assert.equal(claim.rewardReserve.toString(), pool.rewardReserve.toString());
+  });
+
+  it("Caps a pool's total deposits at its capacity", async () => {
+    const ctx = await createTokenPool("capacity");
+    await program.methods
+      .updatePool(null, null, null, null, null, null, null, null, null, new anchor.BN(30 * 1e6))
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    // Fill the pool to exactly its capacity
+    await program.methods
+      .stake(new anchor.BN(30 * 1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    try {
+      await program.methods
+        .stake(new anchor.BN(1), 0, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+      assert.fail("Staking into a full pool should fail");
+    } catch (err) {
+      assert.include(err.toString(), "PoolCapacityReached");
+    }
+
+    const pool = await program.account.pool.fetch(ctx.pool);
+    assert.equal(pool.totalStaked.toString(), pool.maxTotalStaked.toString());
+  });
+
+  it("Treats new positions as already on the reward accumulator", async () => {
+    const ctx = await createTokenPool("accumulator-migration");
+    const user = await program.account.user.fetch(ctx.user);
+    assert.isTrue(user.accumulatorMigrated);
+
+    try {
+      await program.methods
+        .migrateUserToAccumulator()
+        .accounts({
+          pool: ctx.pool,
+          user: ctx.user,
+          owner: provider.wallet.publicKey,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+      assert.fail("Migrating a position twice should fail");
+    } catch (err) {
+      assert.include(err.toString(), "AlreadyMigrated");
+    }
+
+    // Pools created by this version are already on the current layout too
+    const pool = await program.account.pool.fetch(ctx.pool);
+    try {
+      await program.methods
+        .migratePool(pool.poolId)
+        .accounts({
+          pool: ctx.pool,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+          stakeMint: ctx.stakeMint,
+          authorityTokenAccount: ctx.poolAuthorityStake,
+          stakeVault: ctx.stakeVault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
+      assert.fail("Migrating a current pool should fail");
+    } catch (err) {
+      assert.include(err.toString(), "AlreadyMigrated");
+    }
+  });
+
+  it("Rejects an unknown lock type instead of staking flexibly", async () => {
+    const ctx = await createTokenPool("unknown-lock-type");
+
+    // The default pool defines flexible (0) and locked (1) only
+    try {
+      await program.methods
+        .stake(new anchor.BN(1e6), 2, null)
+        .accounts(ctx.stakeAccounts)
+        .rpc();
+      assert.fail("Staking with lock type 2 should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidLockType");
+    }
+
+    const user = await program.account.user.fetch(ctx.user);
+    assert.equal(user.amount.toNumber(), 0);
+  });
+
+  it("Rejects a stake vault other than the pool's associated account", async () => {
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const ctx = await createTokenPool("foreign-vault");
+    const outsider = Keypair.generate();
+    const outsiderStake = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.stakeMint, outsider.publicKey
+    );
+    // Owned by the pool, but not the account the program pins as its vault
+    const strayVault = await createAccount(
+      provider.connection, payer, ctx.stakeMint, ctx.pool, Keypair.generate()
+    );
+
+    // Depositing into an account the staker controls must not count as a stake
+    for (const vault of [outsiderStake.address, strayVault]) {
+      try {
+        await program.methods
+          .stake(new anchor.BN(1e6), 0, null)
+          .accounts({ ...ctx.stakeAccounts, stakeVault: vault })
+          .rpc();
+        assert.fail("Stake into a foreign vault should fail");
+      } catch (err) {
+        assert.include(err.toString(), "InvalidStakeVault");
+      }
+    }
+
+    await program.methods
+      .stake(new anchor.BN(1e6), 0, null)
+      .accounts(ctx.stakeAccounts)
+      .rpc();
+
+    // Nor can an unstake pay out of any other account
+    const { payer: owner, ...unstakeAccounts } = ctx.stakeAccounts;
+    try {
+      await program.methods
+        .unstake(new anchor.BN(1e6))
+        .accounts({ ...unstakeAccounts, authority: owner, stakeVault: strayVault })
+        .rpc();
+      assert.fail("Unstake from a foreign vault should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidStakeVault");
+    }
+
+    // The pool PDA releases the principal without the pool authority signing
+    await program.methods
+      .unstake(new anchor.BN(1e6))
+      .accounts({ ...unstakeAccounts, authority: owner })
+      .rpc();
+    const vault = await getAccount(provider.connection, ctx.stakeVault);
+    assert.equal(Number(vault.amount), 0);
+  });
+
+  it("Burns a position's LST when its account is closed", async () => {
+    const ctx = await createTokenPool("lst-close", false, 0, true);
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const userLst = await getOrCreateAssociatedTokenAccount(
+      provider.connection, payer, ctx.lstMint, provider.wallet.publicKey
+    );
+    const lstAccounts = { lstMint: ctx.lstMint, userLstAccount: userLst.address };
+
+    // No emissions, so closing has no rewards to settle
+    await program.methods
+      .updatePool(new anchor.BN(0), null, null, null, null, null, null, null, null, null)
+      .accounts({ pool: ctx.pool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    await program.methods
+      .stake(new anchor.BN(50 * 1e6), 0, null)
+      .accounts({ ...ctx.stakeAccounts, ...lstAccounts })
+      .rpc();
+
+    const { payer: owner, systemProgram, ...closeAccounts } = ctx.stakeAccounts;
+    try {
+      await program.methods
+        .closeUserAccount()
+        .accounts({ ...closeAccounts, userWallet: owner, authority: owner })
+        .rpc();
+      assert.fail("Closing without burning the LST should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMint");
+    }
+
+    await program.methods
+      .closeUserAccount()
+      .accounts({ ...closeAccounts, ...lstAccounts, userWallet: owner, authority: owner })
+      .rpc();
+
+    const lst = await getAccount(provider.connection, userLst.address);
+    const wallet = await getAccount(provider.connection, ctx.userStake);
+    assert.equal(lst.amount.toString(), "0");
+    assert.equal(wallet.amount.toString(), (1_000 * 1e6).toString());
+  });
+
   after(() => {
     console.log("\n🎉 All tests passed!");
     console.log("\n📊 Summary:");